@@ -0,0 +1,189 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, DeriveInput, LitStr, Path, Token};
+
+/// Implements `RefComponent` for a component type.
+///
+/// Accepts a `#[ref_comp(...)]` attribute with these optional keys:
+/// - `insert = path`: a `fn(&mut World, Entity) -> Self`. Without it the type must implement
+///   `FromWorld`.
+/// - `edit = path`: a `fn(&mut World, Entity, &mut Self)` applied when the entity already has
+///   the component.
+/// - `release = "..."`: one of `"immediate"`, `"delay(frames)"`, `"seconds(secs)"` or `"never"`.
+/// - `removal = "..."`: one of `"reinsert"`, `"invalidate"` or `"panic"`.
+/// - `name = "..."`: the stable name handles to the type are serialized with.
+/// - `despawn_empty`, or `despawn_empty = "recursive"`: despawn the entity once its last
+///   reference-counted component has been released.
+#[proc_macro_derive(RefComponent, attributes(ref_comp))]
+pub fn derive_ref_component(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match derive(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+#[derive(Default)]
+struct RefCompAttrs {
+    insert: Option<Path>,
+    edit: Option<Path>,
+    release: Option<TokenStream2>,
+    removal: Option<TokenStream2>,
+    despawn: Option<TokenStream2>,
+    name: Option<LitStr>,
+}
+
+fn derive(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let attrs = parse_attrs(&input)?;
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let insert = match &attrs.insert {
+        Some(insert) => quote!(#insert(world, entity)),
+        None => quote!(<Self as ::bevy_ref_component::__private::FromWorld>::from_world(world)),
+    };
+    let edit = attrs.edit.as_ref().map(|edit| {
+        quote! {
+            fn edit_fn() -> ::core::option::Option<
+                fn(
+                    &mut ::bevy_ref_component::__private::World,
+                    ::bevy_ref_component::__private::Entity,
+                    &mut Self,
+                ),
+            > {
+                ::core::option::Option::Some(#edit)
+            }
+        }
+    });
+    let release = attrs
+        .release
+        .map(|policy| quote!(.with_release_policy(#policy)));
+    let removal = attrs
+        .removal
+        .map(|policy| quote!(.with_removal_policy(#policy)));
+    let despawn = attrs
+        .despawn
+        .map(|policy| quote!(.with_despawn_policy(#policy)));
+    let stable_name = attrs.name.map(|name| quote!(.with_name(#name)));
+
+    Ok(quote! {
+        impl #impl_generics ::bevy_ref_component::RefComponent for #name #ty_generics #where_clause {
+            fn insert_ref(
+                world: &mut ::bevy_ref_component::__private::World,
+                entity: ::bevy_ref_component::__private::Entity,
+            ) -> Self {
+                #insert
+            }
+
+            #edit
+
+            fn ref_registration() -> ::bevy_ref_component::RefCompRegistration<Self> {
+                ::bevy_ref_component::RefCompRegistration::new()
+                    .with_insert_fn(<Self as ::bevy_ref_component::RefComponent>::insert_ref)
+                    #release
+                    #removal
+                    #despawn
+                    #stable_name
+            }
+        }
+    })
+}
+
+fn parse_attrs(input: &DeriveInput) -> syn::Result<RefCompAttrs> {
+    let mut attrs = RefCompAttrs::default();
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("ref_comp"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("insert") {
+                attrs.insert = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("edit") {
+                attrs.edit = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("release") {
+                let lit: LitStr = meta.value()?.parse()?;
+                attrs.release = Some(parse_release_policy(&lit)?);
+            } else if meta.path.is_ident("removal") {
+                let lit: LitStr = meta.value()?.parse()?;
+                attrs.removal = Some(parse_removal_policy(&lit)?);
+            } else if meta.path.is_ident("name") {
+                attrs.name = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("despawn_empty") {
+                let recursive = meta.input.peek(Token![=]);
+                if recursive {
+                    let lit: LitStr = meta.value()?.parse()?;
+                    if lit.value() != "recursive" {
+                        return Err(syn::Error::new(
+                            lit.span(),
+                            "expected `despawn_empty` or `despawn_empty = \"recursive\"`",
+                        ));
+                    }
+                }
+                attrs.despawn = Some(match recursive {
+                    true => quote!(::bevy_ref_component::DespawnPolicy::DespawnRecursive),
+                    false => quote!(::bevy_ref_component::DespawnPolicy::Despawn),
+                });
+            } else {
+                return Err(meta.error(
+                    "unknown ref_comp attribute, expected one of `insert`, `edit`, `release`, \
+                    `removal`, `name` or `despawn_empty`",
+                ));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(attrs)
+}
+
+fn parse_release_policy(lit: &LitStr) -> syn::Result<TokenStream2> {
+    let value = lit.value();
+    let value = value.trim();
+    let error = || {
+        syn::Error::new(
+            lit.span(),
+            "expected one of \"immediate\", \"delay(frames)\", \"seconds(secs)\" or \"never\"",
+        )
+    };
+    let argument = |prefix: &str| {
+        value
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.strip_prefix('('))
+            .and_then(|rest| rest.strip_suffix(')'))
+            .map(str::trim)
+    };
+
+    let policy = if value == "immediate" {
+        quote!(Immediate)
+    } else if value == "never" {
+        quote!(Never)
+    } else if let Some(frames) = argument("delay") {
+        let frames: u32 = frames.parse().map_err(|_| error())?;
+        quote!(Frames(#frames))
+    } else if let Some(secs) = argument("seconds") {
+        let secs: f64 = secs.parse().map_err(|_| error())?;
+        if !secs.is_finite() || secs < 0.0 {
+            return Err(error());
+        }
+        quote!(Duration(::core::time::Duration::from_secs_f64(#secs)))
+    } else {
+        return Err(error());
+    };
+    Ok(quote!(::bevy_ref_component::ReleasePolicy::#policy))
+}
+
+fn parse_removal_policy(lit: &LitStr) -> syn::Result<TokenStream2> {
+    let policy = match lit.value().as_str() {
+        "reinsert" => quote!(Reinsert),
+        "invalidate" => quote!(Invalidate),
+        "panic" => quote!(Panic),
+        _ => {
+            return Err(syn::Error::new(
+                lit.span(),
+                "expected one of \"reinsert\", \"invalidate\" or \"panic\"",
+            ))
+        }
+    };
+    Ok(quote!(::bevy_ref_component::RemovalPolicy::#policy))
+}
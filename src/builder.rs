@@ -1,64 +1,94 @@
-use bevy::prelude::{Commands, Component, Entity, FromWorld, World};
+use bevy::prelude::{Commands, Component, Entity, FromWorld, Mut, World};
 
-use crate::{EditFn, InsertFn, RefCompExt, RefCompHandle, RefCompServer};
+use crate::{
+    queue_entity_despawn_policy, BoxedEditFn, BoxedInsertFn, DespawnPolicy, RefCompHandle,
+    RefCompServer,
+};
 
 pub struct RefCompBuilder<T: Component> {
     entity: Entity,
-    insert_fn: InsertFn<T>,
-    edit_fn: Option<EditFn<T>>,
+    insert_fn: BoxedInsertFn<T>,
+    edit_fn: Option<BoxedEditFn<T>>,
+    despawn_policy: Option<DespawnPolicy>,
 }
 
 impl<T: Component> RefCompBuilder<T> {
-    pub fn new(entity: Entity, insert_fn: InsertFn<T>) -> Self {
+    pub fn new(
+        entity: Entity,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
+    ) -> Self {
         RefCompBuilder {
             entity,
-            insert_fn,
+            insert_fn: Box::new(insert_fn),
             edit_fn: None,
+            despawn_policy: None,
         }
     }
 
-    pub fn with_edit_fn(mut self, edit_fn: EditFn<T>) -> Self {
-        self.edit_fn = Some(edit_fn);
+    pub fn with_edit_fn(
+        mut self,
+        edit_fn: impl FnOnce(&mut World, Entity, &mut T) + Send + 'static,
+    ) -> Self {
+        self.edit_fn = Some(Box::new(edit_fn));
         self
     }
 
-    pub fn with_insert_fn(mut self, insert_fn: InsertFn<T>) -> Self {
-        self.insert_fn = insert_fn;
+    pub fn with_insert_fn(
+        mut self,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
+    ) -> Self {
+        self.insert_fn = Box::new(insert_fn);
         self
     }
 
+    /// Sets whether the entity is despawned once the last component the server manages on it
+    /// has been released. See [`RefCompServer::set_entity_despawn_policy`].
+    pub fn with_despawn_policy(mut self, despawn_policy: DespawnPolicy) -> Self {
+        self.despawn_policy = Some(despawn_policy);
+        self
+    }
+
+    /// Queues inserting the component. The insert and edit functions only run once, so this
+    /// consumes the builder.
     pub fn build(
-        &mut self,
+        self,
         commands: &mut Commands,
-        ref_comp_server: &mut RefCompServer,
+        ref_comp_server: &RefCompServer,
     ) -> RefCompHandle<T> {
-        ref_comp_server.insert_ref_comp(commands, self.entity, self.insert_fn, self.edit_fn)
+        if let Some(despawn_policy) = self.despawn_policy {
+            queue_entity_despawn_policy(commands, self.entity, despawn_policy);
+        }
+        ref_comp_server.queue_insert(commands, self.entity, self.insert_fn, self.edit_fn)
     }
 
-    pub fn build_world(&mut self, world: &mut World) -> RefCompHandle<T> {
-        world.insert_ref_comp(self.entity, self.insert_fn, self.edit_fn)
+    /// Inserts the component right away. See [`RefCompBuilder::build`].
+    pub fn build_world(self, world: &mut World) -> RefCompHandle<T> {
+        world.resource_scope(|world, mut ref_comp_server: Mut<RefCompServer>| {
+            if let Some(despawn_policy) = self.despawn_policy {
+                ref_comp_server.set_entity_despawn_policy(self.entity, despawn_policy);
+            }
+            ref_comp_server.inner_insert_ref_comp(world, self.entity, self.insert_fn, self.edit_fn)
+        })
     }
 }
 
 impl<T: Component + FromWorld> RefCompBuilder<T> {
     pub fn new_fw(entity: Entity) -> Self {
-        RefCompBuilder {
-            entity,
-            insert_fn: |world: &mut World, _entity| T::from_world(world),
-            edit_fn: None,
-        }
+        RefCompBuilder::new(entity, |world: &mut World, _entity| T::from_world(world))
     }
 }
 pub trait RefCompBuilderExt<T: Component> {
-    fn new(entity: Entity, insert_fn: InsertFn<T>) -> Self;
+    fn new(
+        entity: Entity,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
+    ) -> Self;
 }
 
 impl<T: Component> RefCompBuilderExt<T> for RefCompBuilder<T> {
-    fn new(entity: Entity, insert_fn: InsertFn<T>) -> Self {
-        RefCompBuilder {
-            entity,
-            insert_fn,
-            edit_fn: None,
-        }
+    fn new(
+        entity: Entity,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
+    ) -> Self {
+        RefCompBuilder::new(entity, insert_fn)
     }
 }
@@ -0,0 +1,93 @@
+use std::marker::PhantomData;
+
+use bevy::prelude::{Bundle, Entity};
+use serde::{
+    de::{self, DeserializeSeed},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{RefCompHandle, RefCompHandleId, RefCompServer, RefCompTypeId};
+
+/// A handle is written as its [`RefCompHandleId`], for example in RON:
+///
+/// ```text
+/// (entity: 4294967301, component: "game::Burning")
+/// ```
+///
+/// `entity` is the [`Entity::to_bits`] of the entity, and `component` the stable name of the
+/// component type, see [`RefCompTypeId::name`]. Whether the handle is Strong is not written.
+///
+/// The stable name defaults to the type name, which can change between builds. Types saved in
+/// save games should be given a name with
+/// [`RefCompRegistration::with_name`](crate::RefCompRegistration::with_name) or
+/// `#[ref_comp(name = "...")]`. A warning is logged the first time a type without one is
+/// serialized.
+impl<T: Bundle> Serialize for RefCompHandle<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.id.serialize(serializer)
+    }
+}
+
+/// Reads a Weak handle. Use [`RefCompServer::handle_seed`] to read a Strong one.
+impl<'de, T: Bundle> Deserialize<'de> for RefCompHandle<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_id::<T, D>(deserializer).map(RefCompHandle::weak)
+    }
+}
+
+/// Deserializes a [`RefCompHandle`] as a Strong handle counted by a [`RefCompServer`].
+/// See [`RefCompServer::handle_seed`].
+pub struct RefCompHandleSeed<'a, T: Bundle> {
+    server: &'a RefCompServer,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl RefCompServer {
+    /// A [`DeserializeSeed`] that reads a handle in the format written by its [`Serialize`]
+    /// implementation and makes it Strong.
+    pub fn handle_seed<T: Bundle>(&self) -> RefCompHandleSeed<'_, T> {
+        RefCompHandleSeed {
+            server: self,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'de, T: Bundle> DeserializeSeed<'de> for RefCompHandleSeed<'_, T> {
+    type Value = RefCompHandle<T>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        let id = deserialize_id::<T, D>(deserializer)?;
+        Ok(self.server.get_handle(id))
+    }
+}
+
+fn deserialize_id<'de, T: Bundle, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<RefCompHandleId, D::Error> {
+    // Registers the stable name of `T` in case no handle to it was created yet in this session.
+    let expected = RefCompTypeId::of::<T>();
+    let id = RefCompHandleId::deserialize(deserializer)?;
+    if id.type_id != expected {
+        return Err(de::Error::custom(format!(
+            "expected a handle to `{}`, found one to `{}`",
+            expected.name(),
+            id.type_id.name()
+        )));
+    }
+    Ok(id)
+}
+
+/// Writes entities as their [`Entity::to_bits`], which unlike their own serialized form is a
+/// single number.
+pub(crate) mod entity_bits {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(entity: &Entity, serializer: S) -> Result<S::Ok, S::Error> {
+        entity.to_bits().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Entity, D::Error> {
+        u64::deserialize(deserializer).map(Entity::from_bits)
+    }
+}
@@ -1,29 +1,103 @@
-use std::{any::type_name, marker::PhantomData};
+use std::{
+    any::{Any, TypeId},
+    hash::Hasher,
+    marker::PhantomData,
+    sync::Arc,
+    time::Duration,
+};
 
 use bevy::{
-    ecs::reflect::ReflectComponent,
+    core::FrameCount,
+    ecs::{
+        entity::{EntityMap, MapEntities, MapEntitiesError},
+        reflect::{ReflectComponent, ReflectMapEntities},
+        schedule::{BaseSystemSet, BoxedScheduleLabel, BoxedSystemSet, FreeSystemSet, Schedules},
+        system::{EntityCommands, SystemState},
+        world::EntityMut,
+    },
+    hierarchy::despawn_with_children_recursive,
+    log::warn,
     prelude::{
-        apply_system_buffers, App, Commands, Component, CoreSet, Entity, FromWorld,
-        IntoSystemConfig, IntoSystemSetConfigs, Mut, Plugin, Query, ResMut, Resource, SystemSet,
-        World,
+        Added, App, Bundle, Commands, Component, CoreSchedule, CoreSet, Entity, Event, Events,
+        FromWorld, IntoSystemConfig, IntoSystemSetConfigs, Mut, Plugin, Query, Resource, Schedule,
+        SystemSet, World,
     },
     reflect::{FromReflect, Reflect, ReflectDeserialize, ReflectSerialize},
-    utils::HashMap,
+    time::Time,
+    utils::{
+        label::{DynEq, DynHash},
+        synccell::SyncCell,
+        HashMap, HashSet, Instant,
+    },
 };
 use crossbeam_channel::{Receiver, Sender};
+use parking_lot::{Mutex, MutexGuard};
 
 use serde::{Deserialize, Serialize};
 
+// Lets the derive macro refer to this crate by name from within it, too.
+extern crate self as bevy_ref_component;
+
 #[cfg(test)]
 mod tests;
 
 mod builder;
-pub use builder::RefCompBuilder;
+pub use builder::{RefCompBuilder, RefCompBuilderExt};
+
+mod param;
+pub use param::{RefCompError, RefComps, RefCompsMut};
+
+mod handle_serde;
+pub use handle_serde::RefCompHandleSeed;
+
+mod reflect;
+pub use reflect::RefCompReflectError;
+
+mod registration;
+pub use registration::{RefCompAppExt, RefCompRegistration, RefComponent};
 
-type InsertFn<T> = fn(&mut World, Entity) -> T;
-type EditFn<T> = fn(&mut World, Entity, &mut T);
+pub use bevy_ref_component_macros::RefComponent;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use bevy::prelude::{Entity, FromWorld, World};
+}
+
+mod snapshot;
+pub use snapshot::{RefCompRestoreReport, RefCompSnapshot, RefCompSnapshotEntry, RefCountMismatch};
+
+mod type_id;
+pub use type_id::RefCompTypeId;
+
+/// Creates the component when the target entity does not have one yet.
+pub type InsertFn<T> = fn(&mut World, Entity) -> T;
+/// Edits the component when the target entity already has one.
+pub type EditFn<T> = fn(&mut World, Entity, &mut T);
+type BoxedInsertFn<T> = Box<dyn FnOnce(&mut World, Entity) -> T + Send + 'static>;
+type BoxedEditFn<T> = Box<dyn FnOnce(&mut World, Entity, &mut T) + Send + 'static>;
+type ReinsertFn = Box<dyn FnMut(&mut World, Entity) + Send + 'static>;
+type DefaultInsertFn = Arc<dyn Fn(&mut World, Entity) + Send + Sync + 'static>;
+type DeleteFn = Box<dyn Fn(&mut World, Entity) + Send + Sync + 'static>;
+type ContainsFn = Box<dyn Fn(&World, Entity) -> bool + Send + Sync + 'static>;
+type HandleReattacher = Box<dyn FnMut(&mut World, &Sender<RefChange>) + Send + Sync + 'static>;
+
+/// Adds the [`RefCompServer`] and the system that releases unreferenced components.
+pub struct RefCompPlugin {
+    /// The schedule the release system runs in.
+    pub release_schedule: BoxedScheduleLabel,
+    /// A set to place the release system in, besides [`RefCompSet::Release`]. Base sets are
+    /// supported, and required when `release_schedule` has a default base set.
+    pub release_set: Option<BoxedSystemSet>,
+}
 
-pub struct RefCompPlugin;
+impl Default for RefCompPlugin {
+    fn default() -> Self {
+        RefCompPlugin {
+            release_schedule: Box::new(CoreSchedule::Main),
+            release_set: Some(Box::new(DespawnStage::Parallel)),
+        }
+    }
+}
 
 #[derive(SystemSet, Clone, Debug, PartialEq, Eq, Hash)]
 #[system_set(base)]
@@ -32,10 +106,30 @@ pub enum DespawnStage {
     CommandFlush,
 }
 
+/// The sets the [`RefCompPlugin`] places its systems in, for ordering against.
+#[derive(SystemSet, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RefCompSet {
+    /// Applies reference changes and releases unreferenced components.
+    Release,
+}
+
 impl Plugin for RefCompPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<RefCompServer>()
-            .configure_sets(
+            .add_event::<RefCompInserted>()
+            .add_event::<RefCompEdited>()
+            .add_event::<RefCompReleased>()
+            .add_event::<RefCompInvalidated>()
+            .add_event::<RefCountError>();
+
+        // Only the default placement orders DespawnStage, between Update and PostUpdate.
+        let default_placement = (*self.release_schedule).dyn_eq(&CoreSchedule::Main)
+            && self
+                .release_set
+                .as_ref()
+                .is_some_and(|set| (**set).as_any().is::<DespawnStage>());
+        if default_placement {
+            app.configure_sets(
                 (
                     CoreSet::UpdateFlush,
                     DespawnStage::Parallel,
@@ -43,9 +137,70 @@ impl Plugin for RefCompPlugin {
                     CoreSet::PostUpdate,
                 )
                     .chain(),
-            )
-            .add_system(delete_unreferenced_components.in_base_set(DespawnStage::Parallel))
-            .add_system(apply_system_buffers.in_base_set(DespawnStage::CommandFlush));
+            );
+        }
+
+        let mut release = delete_unreferenced_components
+            .into_config()
+            .in_set(RefCompSet::Release);
+        if let Some(set) = &self.release_set {
+            release = match set.is_base() {
+                true => release.in_base_set(BoxedSet::<true>(set.clone())),
+                false => release.in_set(BoxedSet::<false>(set.clone())),
+            };
+        }
+
+        let mut schedules = app.world.resource_mut::<Schedules>();
+        if !schedules.contains(&*self.release_schedule) {
+            schedules.insert(self.release_schedule.clone(), Schedule::new());
+        }
+        schedules
+            .get_mut(&*self.release_schedule)
+            .expect("the schedule was just inserted")
+            .add_system(release);
+    }
+}
+
+/// Passes a [`BoxedSystemSet`] where a [`SystemSet`] is expected, comparing and hashing equal
+/// to the set it wraps. `BASE` must match [`SystemSet::is_base`] of the wrapped set.
+#[derive(Debug)]
+struct BoxedSet<const BASE: bool>(BoxedSystemSet);
+
+impl BaseSystemSet for BoxedSet<true> {}
+
+impl FreeSystemSet for BoxedSet<false> {}
+
+impl<const BASE: bool> DynEq for BoxedSet<BASE> {
+    fn as_any(&self) -> &dyn Any {
+        (*self.0).as_any()
+    }
+
+    fn dyn_eq(&self, other: &dyn DynEq) -> bool {
+        (*self.0).dyn_eq(other)
+    }
+}
+
+impl<const BASE: bool> DynHash for BoxedSet<BASE> {
+    fn as_dyn_eq(&self) -> &dyn DynEq {
+        (*self.0).as_dyn_eq()
+    }
+
+    fn dyn_hash(&self, state: &mut dyn Hasher) {
+        (*self.0).dyn_hash(state)
+    }
+}
+
+impl<const BASE: bool> SystemSet for BoxedSet<BASE> {
+    fn system_type(&self) -> Option<TypeId> {
+        self.0.system_type()
+    }
+
+    fn is_base(&self) -> bool {
+        self.0.is_base()
+    }
+
+    fn dyn_clone(&self) -> BoxedSystemSet {
+        self.0.dyn_clone()
     }
 }
 // *****************************************************************************************
@@ -54,12 +209,115 @@ impl Plugin for RefCompPlugin {
 #[derive(Default, Resource)]
 pub struct RefCompServer {
     channel: RefChangeChannel,
+    /// Changes taken off the channel by [`RefCompServer::ref_count`] that have not been applied
+    /// yet.
+    received: Mutex<ReceivedRefChanges>,
     ref_counts: HashMap<RefCompHandleId, usize>,
-    comp_spawner: HashMap<String, RefComponentSpawner>,
+    /// Strong handle counts for ids whose entity has been despawned, or whose component was
+    /// invalidated by an external removal.
+    orphaned: HashMap<RefCompHandleId, usize>,
+    comp_spawner: HashMap<RefCompTypeId, RefComponentSpawner>,
+    /// Reinsert functions kept for reflected components whose type uses
+    /// [`RemovalPolicy::Reinsert`].
+    reinsert_fns: HashMap<RefCompHandleId, SyncCell<ReinsertFn>>,
+    /// Unreferenced components waiting on their [`ReleasePolicy`].
+    pending_releases: HashMap<RefCompHandleId, PendingRelease>,
+    /// Ids whose component the server has seen on their entity. Only these are checked for
+    /// external removal, so that a queued insert is not taken for one.
+    present: HashSet<RefCompHandleId>,
+    release_policy: ReleasePolicy,
+    entity_despawn_policies: HashMap<Entity, DespawnPolicy>,
+    /// The strongest [`DespawnPolicy`] of the component types inserted on each entity, applied
+    /// once the entity holds no managed components, whichever is released last.
+    type_despawn_policies: HashMap<Entity, DespawnPolicy>,
+    /// Makes handle components that were loaded through reflection Strong again, by handle type.
+    handle_reattachers: HashMap<RefCompTypeId, HandleReattacher>,
+    /// How many times unreferenced components have been released so far. Counts frames for
+    /// [`ReleasePolicy::Frames`] when the world has no [`FrameCount`].
+    release_passes: u32,
+    /// Measures [`ReleasePolicy::Duration`] in real time when the world has no [`Time`].
+    fallback_clock: Option<Instant>,
+    warned_missing_time: bool,
 }
 
 impl RefCompServer {
-    pub fn get_handle<T: Component, I: Into<RefCompHandleId>>(&self, id: I) -> RefCompHandle<T> {
+    /// Returns false if the entity of `id` has been despawned from `world`, or the server has
+    /// seen the component invalidated by an external removal.
+    ///
+    /// Entities are compared by generation, so this stays false after the entity index is
+    /// reused.
+    pub fn is_alive(&self, world: &World, id: &RefCompHandleId) -> bool {
+        world.entities().contains(id.entity) && !self.is_invalidated(id)
+    }
+
+    /// Whether the server has seen the entity of `id` despawned or the component invalidated,
+    /// while strong handles to it are still alive.
+    fn is_invalidated(&self, id: &RefCompHandleId) -> bool {
+        self.orphaned.contains_key(id)
+    }
+
+    /// The number of strong handles pointing at `id`, including changes that have not been
+    /// applied yet.
+    pub fn ref_count(&self, id: &RefCompHandleId) -> usize {
+        let received = self.receive_ref_changes();
+        let delta = received.deltas.get(id).copied().unwrap_or(0);
+        self.applied_ref_count(id).saturating_add_signed(delta)
+    }
+
+    /// The number of strong handles pointing at the `T` of `entity`.
+    pub fn ref_count_of<T: Bundle>(&self, entity: Entity) -> usize {
+        self.ref_count(&RefCompHandleId::new::<T>(entity))
+    }
+
+    /// Iterate over every id that has strong handles pointing at it, paired with their number.
+    /// See [`RefCompServer::ref_count`].
+    pub fn iter_ref_counts(&self) -> impl Iterator<Item = (RefCompHandleId, usize)> {
+        let received = self.receive_ref_changes();
+        let ids: HashSet<RefCompHandleId> = self
+            .ref_counts
+            .keys()
+            .chain(self.orphaned.keys())
+            .chain(received.deltas.keys())
+            .copied()
+            .collect();
+        let counts: Vec<_> = ids
+            .into_iter()
+            .map(|id| {
+                let delta = received.deltas.get(&id).copied().unwrap_or(0);
+                (id, self.applied_ref_count(&id).saturating_add_signed(delta))
+            })
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        counts.into_iter()
+    }
+
+    /// Whether the component of `id` is still counted or waiting to be released.
+    fn is_tracked(&self, id: &RefCompHandleId) -> bool {
+        !self.is_invalidated(id)
+            && (self.ref_counts.contains_key(id)
+                || self.pending_releases.contains_key(id)
+                || self.ref_count(id) > 0)
+    }
+
+    fn applied_ref_count(&self, id: &RefCompHandleId) -> usize {
+        self.ref_counts
+            .get(id)
+            .or_else(|| self.orphaned.get(id))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Moves everything waiting on the channel into `received`, so it can be counted without
+    /// mutable access to the server.
+    fn receive_ref_changes(&self) -> MutexGuard<'_, ReceivedRefChanges> {
+        let mut received = self.received.lock();
+        for ref_change in self.channel.receiver.try_iter() {
+            received.push(ref_change);
+        }
+        received
+    }
+
+    pub fn get_handle<T: Bundle, I: Into<RefCompHandleId>>(&self, id: I) -> RefCompHandle<T> {
         let sender = self.channel.sender.clone();
         RefCompHandle::strong(id.into(), sender)
     }
@@ -69,106 +327,154 @@ impl RefCompServer {
         RefCompHandleUntyped::strong(id.into(), sender)
     }
 
+    fn register_spawner<T: Bundle>(&mut self) {
+        self.register_spawner_untyped(
+            RefCompTypeId::of::<T>(),
+            delete_component::<T>,
+            contains_bundle::<T>,
+        );
+    }
+
+    fn register_spawner_untyped(
+        &mut self,
+        type_id: RefCompTypeId,
+        delete: impl Fn(&mut World, Entity) + Send + Sync + 'static,
+        contains: impl Fn(&World, Entity) -> bool + Send + Sync + 'static,
+    ) {
+        self.comp_spawner
+            .entry(type_id)
+            .or_insert_with(|| RefComponentSpawner {
+                delete: Box::new(delete),
+                contains: Box::new(contains),
+                removal_policy: RemovalPolicy::default(),
+                release_policy: None,
+                despawn_policy: DespawnPolicy::default(),
+                default_insert: None,
+            });
+    }
+
     fn inner_insert_ref_comp_from_world<T: Component + FromWorld>(
         &mut self,
         world: &mut World,
         entity: Entity,
-        edit_fn: Option<EditFn<T>>,
+        edit_fn: Option<impl FnOnce(&mut World, Entity, &mut T)>,
     ) -> RefCompHandle<T> {
-        let handle_id = RefCompHandleId::new::<T>(entity);
-
-        if !self.comp_spawner.contains_key(&handle_id.type_id) {
-            self.comp_spawner.insert(
-                type_name::<T>().to_string(),
-                RefComponentSpawner {
-                    delete: delete_component::<T>,
-                },
-            );
-        }
-
-        match world.entity(handle_id.entity).contains::<T>() {
-            true => {
-                if let Some(edit_fn) = edit_fn {
-                    if let Some(mut comp) = world.entity_mut(entity).take::<T>() {
-                        edit_fn(world, entity, &mut comp);
-                        world.entity_mut(entity).insert(comp);
-                    }
-                }
-            }
-            false => {
-                let comp = T::from_world(world);
-                world.entity_mut(handle_id.entity).insert(comp);
-            }
-        }
-        self.get_handle(handle_id)
+        self.inner_insert_ref_comp(world, entity, from_world::<T>, edit_fn)
     }
 
-    fn inner_insert_ref_comp<T: Component>(
+    fn inner_insert_ref_comp<T: Bundle>(
         &mut self,
         world: &mut World,
         entity: Entity,
-        insert_fn: InsertFn<T>,
-        edit_fn: Option<EditFn<T>>,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
+        edit_fn: Option<impl FnOnce(&mut World, Entity, &mut T)>,
     ) -> RefCompHandle<T> {
         let handle_id = RefCompHandleId::new::<T>(entity);
-
-        if !self.comp_spawner.contains_key(&handle_id.type_id) {
-            self.comp_spawner.insert(
-                type_name::<T>().to_string(),
-                RefComponentSpawner {
-                    delete: delete_component::<T>,
-                },
-            );
+        self.register_spawner::<T>();
+        if insert_or_edit(world, handle_id.entity, insert_fn, edit_fn) {
+            self.track_insert(handle_id);
         }
-
-        match world.entity(handle_id.entity).contains::<T>() {
-            true => {
-                if let Some(edit_fn) = edit_fn {
-                    if let Some(mut comp) = world.entity_mut(entity).take::<T>() {
-                        edit_fn(world, entity, &mut comp);
-                        world.entity_mut(entity).insert(comp);
-                    }
-                }
-            }
-            false => {
-                let comp = insert_fn(world, handle_id.entity);
-                world.entity_mut(handle_id.entity).insert(comp);
-            }
-        }
-
         self.get_handle(handle_id)
     }
 
+    /// Spawns a new entity with `bundle` that is despawned once the last strong handle to it
+    /// is dropped.
+    pub fn spawn_ref(
+        &self,
+        commands: &mut Commands,
+        bundle: impl Bundle,
+    ) -> RefCompHandle<RefEntity> {
+        let entity = commands.spawn(bundle).id();
+        queue_entity_despawn_policy(commands, entity, DespawnPolicy::Despawn);
+        self.insert_ref_comp_fw::<RefEntity>(commands, entity, None)
+    }
+
     pub fn insert_ref_comp_fw<T: Component + FromWorld>(
-        &mut self,
+        &self,
         commands: &mut Commands,
         entity: Entity,
         edit_fn: Option<EditFn<T>>,
     ) -> RefCompHandle<T> {
-        let handle_id = RefCompHandleId::new::<T>(entity);
+        self.queue_insert(commands, entity, from_world::<T>, edit_fn)
+    }
 
-        if !self.comp_spawner.contains_key(&handle_id.type_id) {
-            self.comp_spawner.insert(
-                type_name::<T>().to_string(),
-                RefComponentSpawner {
-                    delete: delete_component::<T>,
-                },
-            );
-        }
+    /// Like [`RefCompServer::insert_ref_comp_fw`], with an edit function that may capture
+    /// values.
+    pub fn insert_ref_comp_fw_with_edit<T: Component + FromWorld>(
+        &self,
+        commands: &mut Commands,
+        entity: Entity,
+        edit_fn: impl FnOnce(&mut World, Entity, &mut T) + Send + 'static,
+    ) -> RefCompHandle<T> {
+        self.queue_insert(commands, entity, from_world::<T>, Some(edit_fn))
+    }
+
+    pub fn insert_ref_comp<T: Component>(
+        &self,
+        commands: &mut Commands,
+        entity: Entity,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
+        edit_fn: Option<EditFn<T>>,
+    ) -> RefCompHandle<T> {
+        self.queue_insert(commands, entity, insert_fn, edit_fn)
+    }
+
+    /// Like [`RefCompServer::insert_ref_comp`], with an edit function that may capture values.
+    pub fn insert_ref_comp_with_edit<T: Component>(
+        &self,
+        commands: &mut Commands,
+        entity: Entity,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
+        edit_fn: impl FnOnce(&mut World, Entity, &mut T) + Send + 'static,
+    ) -> RefCompHandle<T> {
+        self.queue_insert(commands, entity, insert_fn, Some(edit_fn))
+    }
+
+    /// Inserts a reference-counted bundle. Once the last strong handle to it is dropped, every
+    /// component of the bundle is removed together.
+    ///
+    /// The bundle only counts as present if the entity has all of its components.
+    ///
+    /// The type is registered once the command is applied, so this only needs shared access to
+    /// the server.
+    pub fn insert_ref_bundle<B: Bundle>(
+        &self,
+        commands: &mut Commands,
+        entity: Entity,
+        insert_fn: impl FnOnce(&mut World, Entity) -> B + Send + 'static,
+        edit_fn: Option<EditFn<B>>,
+    ) -> RefCompHandle<B> {
+        self.queue_insert(commands, entity, insert_fn, edit_fn)
+    }
+
+    /// Like [`RefCompServer::insert_ref_bundle`], with an edit function that may capture values.
+    pub fn insert_ref_bundle_with_edit<B: Bundle>(
+        &self,
+        commands: &mut Commands,
+        entity: Entity,
+        insert_fn: impl FnOnce(&mut World, Entity) -> B + Send + 'static,
+        edit_fn: impl FnOnce(&mut World, Entity, &mut B) + Send + 'static,
+    ) -> RefCompHandle<B> {
+        self.queue_insert(commands, entity, insert_fn, Some(edit_fn))
+    }
+
+    /// Queues inserting `B` on `entity`, or editing it if the entity already has one.
+    fn queue_insert<B: Bundle>(
+        &self,
+        commands: &mut Commands,
+        entity: Entity,
+        insert_fn: impl FnOnce(&mut World, Entity) -> B + Send + 'static,
+        edit_fn: Option<impl FnOnce(&mut World, Entity, &mut B) + Send + 'static>,
+    ) -> RefCompHandle<B> {
+        let handle_id = RefCompHandleId::new::<B>(entity);
 
         commands.add(move |world: &mut World| {
-            match world.entity(handle_id.entity).contains::<T>() {
-                true => {
-                    if let Some(edit_fn) = edit_fn {
-                        if let Some(mut comp) = world.entity_mut(entity).take::<T>() {
-                            edit_fn(world, entity, &mut comp);
-                            world.entity_mut(entity).insert(comp);
-                        }
-                    }
-                }
-                false => {
-                    let comp = T::from_world(world);
-                    world.entity_mut(handle_id.entity).insert(comp);
+            if let Some(mut server) = world.get_resource_mut::<RefCompServer>() {
+                server.register_spawner::<B>();
+            }
+            if insert_or_edit(world, entity, insert_fn, edit_fn) {
+                if let Some(mut server) = world.get_resource_mut::<RefCompServer>() {
+                    server.track_insert(handle_id);
                 }
             }
         });
@@ -176,42 +482,455 @@ impl RefCompServer {
         self.get_handle(handle_id)
     }
 
-    pub fn insert_ref_comp<T: Component>(
-        &mut self,
+    /// Inserts `T` with the insert function it was registered with, see
+    /// [`RefCompRegistration::with_insert_fn`].
+    pub fn insert_ref_comp_default<T: Bundle>(
+        &self,
         commands: &mut Commands,
         entity: Entity,
-        insert_fn: InsertFn<T>,
-        edit_fn: Option<EditFn<T>>,
     ) -> RefCompHandle<T> {
         let handle_id = RefCompHandleId::new::<T>(entity);
 
-        if !self.comp_spawner.contains_key(&handle_id.type_id) {
-            self.comp_spawner.insert(
-                type_name::<T>().to_string(),
-                RefComponentSpawner {
-                    delete: delete_component::<T>,
-                },
-            );
+        commands.add(move |world: &mut World| {
+            if contains_bundle::<T>(world, entity) {
+                return;
+            }
+            let default_insert = world
+                .get_resource::<RefCompServer>()
+                .and_then(|server| server.comp_spawner.get(&handle_id.type_id))
+                .and_then(|spawner| spawner.default_insert.clone());
+            let Some(default_insert) = default_insert else {
+                warn!(
+                    "{:?} has no registered insert function. Register one with \
+                    `App::register_ref_component_with`.",
+                    handle_id
+                );
+                return;
+            };
+            default_insert(world, entity);
+            send_event(world, RefCompInserted { id: handle_id });
+            world
+                .resource_mut::<RefCompServer>()
+                .track_insert(handle_id);
+        });
+
+        self.get_handle(handle_id)
+    }
+
+    /// Sets when components are removed after their last strong handle was dropped, for types
+    /// that do not have their own policy set with [`RefCompServer::set_release_policy`].
+    pub fn set_default_release_policy(&mut self, policy: ReleasePolicy) {
+        self.release_policy = policy;
+    }
+
+    /// Sets when components of type `T` are removed after their last strong handle was dropped.
+    pub fn set_release_policy<T: Bundle>(&mut self, policy: ReleasePolicy) {
+        self.register_spawner::<T>();
+        if let Some(spawner) = self.comp_spawner.get_mut(&RefCompTypeId::of::<T>()) {
+            spawner.release_policy = Some(policy);
         }
+    }
 
-        commands.add(move |world: &mut World| {
-            match world.entity(handle_id.entity).contains::<T>() {
-                true => {
-                    if let Some(edit_fn) = edit_fn {
-                        if let Some(mut comp) = world.entity_mut(entity).take::<T>() {
-                            edit_fn(world, entity, &mut comp);
-                            world.entity_mut(entity).insert(comp);
-                        }
+    /// Sets whether entities are despawned once the last component the server manages on them
+    /// has been released, for entities that hold a `T`. The strongest policy of the types an
+    /// entity held applies.
+    pub fn set_despawn_policy<T: Bundle>(&mut self, policy: DespawnPolicy) {
+        self.register_spawner::<T>();
+        if let Some(spawner) = self.comp_spawner.get_mut(&RefCompTypeId::of::<T>()) {
+            spawner.despawn_policy = policy;
+        }
+    }
+
+    /// Sets whether `entity` is despawned once the last component the server manages on it has
+    /// been released. Overrides the policy of the component types.
+    pub fn set_entity_despawn_policy(&mut self, entity: Entity, policy: DespawnPolicy) {
+        self.entity_despawn_policies.insert(entity, policy);
+    }
+
+    /// Sets what the server does when a component of type `T` it manages is removed by
+    /// something else while strong handles to it are still alive.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `policy` is [`RemovalPolicy::Reinsert`] and `T` was not registered with an
+    /// insert function, see [`RefCompRegistration::with_insert_fn`].
+    pub fn set_removal_policy<T: Bundle>(&mut self, policy: RemovalPolicy) {
+        self.register_spawner::<T>();
+        let spawner = self
+            .comp_spawner
+            .get_mut(&RefCompTypeId::of::<T>())
+            .expect("the spawner was just registered");
+        assert_reinsertable::<T>(policy, spawner.default_insert.is_some());
+        spawner.removal_policy = policy;
+    }
+
+    /// Bookkeeping for a component the server has just inserted.
+    ///
+    /// Insert functions only run once, so [`RemovalPolicy::Reinsert`] reinserts the component
+    /// with the insert function its type was registered with.
+    fn track_insert(&mut self, handle_id: RefCompHandleId) {
+        self.revive(handle_id);
+        self.present.insert(handle_id);
+        self.record_despawn_policy(handle_id);
+    }
+
+    /// Remembers the despawn policy of the type of `handle_id` for its entity, if it is stronger
+    /// than the one recorded so far.
+    fn record_despawn_policy(&mut self, handle_id: RefCompHandleId) {
+        let Some(spawner) = self.comp_spawner.get(&handle_id.type_id) else {
+            return;
+        };
+        if spawner.despawn_policy != DespawnPolicy::Keep {
+            let policy = self
+                .type_despawn_policies
+                .entry(handle_id.entity)
+                .or_insert(spawner.despawn_policy);
+            *policy = (*policy).max(spawner.despawn_policy);
+        }
+    }
+
+    /// Like [`RefCompServer::track_insert`], also keeping `reinsert_fn` to reinsert the
+    /// component with if its type uses [`RemovalPolicy::Reinsert`].
+    fn track_insert_untyped(
+        &mut self,
+        handle_id: RefCompHandleId,
+        reinsert_fn: impl FnMut(&mut World, Entity) + Send + 'static,
+    ) {
+        self.track_insert(handle_id);
+
+        let reinsert = self
+            .comp_spawner
+            .get(&handle_id.type_id)
+            .is_some_and(|spawner| spawner.removal_policy == RemovalPolicy::Reinsert);
+        if reinsert {
+            let reinsert_fn: ReinsertFn = Box::new(reinsert_fn);
+            self.reinsert_fns
+                .insert(handle_id, SyncCell::new(reinsert_fn));
+        }
+    }
+
+    /// Applies all pending reference changes and removes the components that are no longer
+    /// referenced.
+    fn release_unreferenced(&mut self, world: &mut World) -> RefCompReleaseReport {
+        let mut report = RefCompReleaseReport::default();
+        for reattach in self.handle_reattachers.values_mut() {
+            reattach(world, &self.channel.sender);
+        }
+        let received = self.received.get_mut();
+        received.deltas.clear();
+        let mut ref_changes = std::mem::take(&mut received.changes);
+        ref_changes.extend(self.channel.receiver.try_iter());
+        // Ids whose count reached zero at some point during this batch. Whether they are
+        // released is decided from their final count, since a later increment in the same batch
+        // may have brought it back up.
+        let mut zeroed: HashSet<RefCompHandleId> = HashSet::default();
+        for ref_change in ref_changes {
+            match ref_change {
+                RefChange::Increment(handle_id) => self.increment(handle_id),
+                RefChange::Decrement(handle_id) => {
+                    self.decrement(world, handle_id, &mut zeroed);
+                }
+                RefChange::Remap(old_id, new_id) => {
+                    self.increment(new_id);
+                    if self.decrement(world, old_id, &mut zeroed) {
+                        self.remap(old_id, new_id);
                     }
                 }
-                false => {
-                    let comp = insert_fn(world, entity);
-                    world.entity_mut(handle_id.entity).insert(comp);
+            }
+        }
+
+        // Entities that were despawned take their components with them, so only remember how
+        // many strong handles still point at them. Entities reserved by commands that have not
+        // been applied yet are not despawned.
+        let despawned: Vec<RefCompHandleId> = self
+            .ref_counts
+            .keys()
+            .filter(|handle_id| !world.entities().contains(handle_id.entity))
+            .copied()
+            .collect();
+        for handle_id in despawned {
+            self.entity_despawn_policies.remove(&handle_id.entity);
+            self.invalidate(handle_id);
+            report.invalidated.push(handle_id);
+        }
+        // Unreferenced components of despawned entities have nothing left to release.
+        let despawned: Vec<RefCompHandleId> = self
+            .pending_releases
+            .keys()
+            .filter(|handle_id| !world.entities().contains(handle_id.entity))
+            .copied()
+            .collect();
+        for handle_id in despawned {
+            self.pending_releases.remove(&handle_id);
+            self.reinsert_fns.remove(&handle_id);
+            self.present.remove(&handle_id);
+        }
+        self.entity_despawn_policies
+            .retain(|entity, _| world.entities().contains(*entity));
+        self.type_despawn_policies
+            .retain(|entity, _| world.entities().contains(*entity));
+
+        let mut removed = Vec::new();
+        for handle_id in self.ref_counts.keys() {
+            let Some(spawner) = self.comp_spawner.get(&handle_id.type_id) else {
+                continue;
+            };
+            if (spawner.contains)(world, handle_id.entity) {
+                self.present.insert(*handle_id);
+            } else if self.present.contains(handle_id) {
+                removed.push(*handle_id);
+            }
+        }
+        for handle_id in removed {
+            if !self.handle_external_removal(world, handle_id) {
+                report.invalidated.push(handle_id);
+            }
+        }
+
+        let now = self.now(world);
+        for handle_id in zeroed {
+            if !self.ref_counts.contains_key(&handle_id) {
+                self.pending_releases.insert(handle_id, now);
+            }
+        }
+
+        let mut waits_on_duration = false;
+        let ready: Vec<RefCompHandleId> = self
+            .pending_releases
+            .iter()
+            .filter(|(handle_id, pending)| {
+                let policy = self
+                    .comp_spawner
+                    .get(&handle_id.type_id)
+                    .and_then(|spawner| spawner.release_policy)
+                    .unwrap_or(self.release_policy);
+                match policy {
+                    ReleasePolicy::Immediate => true,
+                    ReleasePolicy::Frames(frames) => {
+                        now.frame.wrapping_sub(pending.frame) >= frames
+                    }
+                    ReleasePolicy::Duration(duration) => {
+                        waits_on_duration = true;
+                        now.elapsed - pending.elapsed >= duration
+                    }
+                    ReleasePolicy::Never => false,
                 }
+            })
+            .map(|(handle_id, _)| *handle_id)
+            .collect();
+        if waits_on_duration && !world.contains_resource::<Time>() && !self.warned_missing_time {
+            warn!(
+                "The world has no Time resource, so ReleasePolicy::Duration is measured in real \
+                time. Add bevy's TimePlugin to measure it in game time."
+            );
+            self.warned_missing_time = true;
+        }
+        self.release(world, ready, &mut report);
+        self.release_passes = self.release_passes.wrapping_add(1);
+        report
+    }
+
+    fn increment(&mut self, handle_id: RefCompHandleId) {
+        match self.orphaned.get_mut(&handle_id) {
+            Some(count) => *count += 1,
+            None => {
+                // Re-acquiring a handle cancels a pending release.
+                self.pending_releases.remove(&handle_id);
+                *self.ref_counts.entry(handle_id).or_insert(0) += 1;
             }
-        });
+        }
+    }
 
-        self.get_handle(handle_id)
+    /// Returns true if this was the last strong handle to `handle_id`.
+    fn decrement(
+        &mut self,
+        world: &mut World,
+        handle_id: RefCompHandleId,
+        zeroed: &mut HashSet<RefCompHandleId>,
+    ) -> bool {
+        if let Some(count) = self.orphaned.get_mut(&handle_id) {
+            *count -= 1;
+            if *count == 0 {
+                self.orphaned.remove(&handle_id);
+                return true;
+            }
+            return false;
+        }
+        let Some(count) = self.ref_counts.get_mut(&handle_id) else {
+            let error = RefCountError::UnmatchedDecrement(handle_id);
+            warn!("{}", error);
+            send_event(world, error);
+            return false;
+        };
+        *count -= 1;
+        if *count == 0 {
+            self.ref_counts.remove(&handle_id);
+            zeroed.insert(handle_id);
+            return true;
+        }
+        false
+    }
+
+    /// Moves the bookkeeping of `old_id` to `new_id` once every strong handle to it was
+    /// remapped. Despawn policies of the old entity only move once no strong handle points at
+    /// any of its components.
+    fn remap(&mut self, old_id: RefCompHandleId, new_id: RefCompHandleId) {
+        if self.present.remove(&old_id) {
+            self.present.insert(new_id);
+        }
+        if let Some(reinsert_fn) = self.reinsert_fns.remove(&old_id) {
+            self.reinsert_fns.entry(new_id).or_insert(reinsert_fn);
+        }
+        if old_id.entity == new_id.entity
+            || self.ref_counts.keys().any(|id| id.entity == old_id.entity)
+        {
+            return;
+        }
+        if let Some(policy) = self.entity_despawn_policies.remove(&old_id.entity) {
+            self.entity_despawn_policies
+                .entry(new_id.entity)
+                .or_insert(policy);
+        }
+        if let Some(policy) = self.type_despawn_policies.remove(&old_id.entity) {
+            let new_policy = self
+                .type_despawn_policies
+                .entry(new_id.entity)
+                .or_insert(policy);
+            *new_policy = (*new_policy).max(policy);
+        }
+    }
+
+    fn now(&mut self, world: &World) -> PendingRelease {
+        let elapsed = match world.get_resource::<Time>() {
+            Some(time) => time.elapsed(),
+            None => self
+                .fallback_clock
+                .get_or_insert_with(Instant::now)
+                .elapsed(),
+        };
+        let frame = match world.get_resource::<FrameCount>() {
+            Some(frame_count) => frame_count.0,
+            None => self.release_passes,
+        };
+        PendingRelease { frame, elapsed }
+    }
+
+    /// Removes every component that is no longer referenced but still waiting on its
+    /// [`ReleasePolicy`], including those using [`ReleasePolicy::Never`].
+    pub fn sweep(&mut self, world: &mut World) -> RefCompReleaseReport {
+        let mut report = RefCompReleaseReport::default();
+        let pending: Vec<RefCompHandleId> = self.pending_releases.keys().copied().collect();
+        self.release(world, pending, &mut report);
+        report
+    }
+
+    fn release(
+        &mut self,
+        world: &mut World,
+        handle_ids: Vec<RefCompHandleId>,
+        report: &mut RefCompReleaseReport,
+    ) {
+        let mut emptied: HashSet<Entity> = HashSet::default();
+        for handle_id in handle_ids {
+            self.pending_releases.remove(&handle_id);
+            self.reinsert_fns.remove(&handle_id);
+            self.present.remove(&handle_id);
+            let Some(spawner) = self.comp_spawner.get(&handle_id.type_id) else {
+                warn!(
+                    "{:?} is no longer referenced but its type was never registered with the \
+                    RefCompServer, so it cannot be removed. Register it with \
+                    `App::register_ref_component`.",
+                    handle_id
+                );
+                continue;
+            };
+            if world.get_entity(handle_id.entity).is_some() {
+                (spawner.delete)(world, handle_id.entity);
+                send_event(world, RefCompReleased { id: handle_id });
+                report.released.push(handle_id);
+                emptied.insert(handle_id.entity);
+                // Also covers policies set after the component was inserted.
+                self.record_despawn_policy(handle_id);
+            }
+        }
+
+        // Only despawn entities that no longer hold any component the server manages.
+        for handle_id in self.ref_counts.keys().chain(self.pending_releases.keys()) {
+            emptied.remove(&handle_id.entity);
+        }
+        for entity in emptied {
+            let policy = self
+                .entity_despawn_policies
+                .get(&entity)
+                .or_else(|| self.type_despawn_policies.get(&entity))
+                .copied()
+                .unwrap_or_default();
+            if policy == DespawnPolicy::Keep {
+                continue;
+            }
+            self.entity_despawn_policies.remove(&entity);
+            self.type_despawn_policies.remove(&entity);
+            match policy {
+                DespawnPolicy::Keep => continue,
+                DespawnPolicy::Despawn => {
+                    world.despawn(entity);
+                }
+                DespawnPolicy::DespawnRecursive => despawn_with_children_recursive(world, entity),
+            }
+            report.despawned.push(entity);
+        }
+    }
+
+    /// Returns whether the component was reinserted rather than invalidated.
+    fn handle_external_removal(&mut self, world: &mut World, handle_id: RefCompHandleId) -> bool {
+        let policy = self.comp_spawner[&handle_id.type_id].removal_policy;
+        match policy {
+            RemovalPolicy::Reinsert => {
+                if let Some(reinsert_fn) = self.reinsert_fns.get_mut(&handle_id) {
+                    (reinsert_fn.get())(world, handle_id.entity);
+                    send_event(world, RefCompInserted { id: handle_id });
+                    return true;
+                }
+                if let Some(default_insert) =
+                    self.comp_spawner[&handle_id.type_id].default_insert.clone()
+                {
+                    default_insert(world, handle_id.entity);
+                    send_event(world, RefCompInserted { id: handle_id });
+                    return true;
+                }
+                warn!(
+                    "{:?} was removed but it has no insert function to reinsert it with, invalidating its handles",
+                    handle_id
+                );
+            }
+            RemovalPolicy::Invalidate => {}
+            RemovalPolicy::Panic => {
+                #[cfg(debug_assertions)]
+                panic!(
+                    "{:?} was removed while strong handles to it were still alive",
+                    handle_id
+                );
+            }
+        }
+        self.invalidate(handle_id);
+        send_event(world, RefCompInvalidated { id: handle_id });
+        false
+    }
+
+    /// Handles invalidated by an earlier removal refer to the newly inserted component again.
+    fn revive(&mut self, handle_id: RefCompHandleId) {
+        if let Some(count) = self.orphaned.remove(&handle_id) {
+            *self.ref_counts.entry(handle_id).or_insert(0) += count;
+        }
+    }
+
+    /// Stops tracking `handle_id`, only remembering how many strong handles still point at it.
+    fn invalidate(&mut self, handle_id: RefCompHandleId) {
+        self.reinsert_fns.remove(&handle_id);
+        self.present.remove(&handle_id);
+        if let Some(count) = self.ref_counts.remove(&handle_id) {
+            self.orphaned.insert(handle_id, count);
+        }
     }
 }
 
@@ -246,35 +965,8 @@ fn free_unused_components(
     }
 }
  */
-fn delete_unreferenced_components(
-    mut server: ResMut<RefCompServer>,
-    valid_query: Query<Entity>,
-    mut commands: Commands,
-) {
-    let ref_changes: Vec<RefChange> = server.channel.receiver.try_iter().collect();
-    let ref_counts = &mut server.ref_counts;
-    let mut despawn_list: Vec<RefCompHandleId> = Vec::new();
-    for ref_change in ref_changes {
-        match ref_change {
-            RefChange::Increment(handle_id) => *ref_counts.entry(handle_id).or_insert(0) += 1,
-            RefChange::Decrement(handle_id) => {
-                let entry = ref_counts.entry(handle_id.clone()).or_insert(0);
-                *entry -= 1;
-                if *entry == 0 {
-                    ref_counts.remove(&handle_id);
-                    despawn_list.push(handle_id);
-                }
-            }
-        }
-    }
-    let comp_spawner = &server.comp_spawner;
-    for handle_id in despawn_list {
-        if let Some(spawner) = comp_spawner.get(&handle_id.type_id) {
-            if valid_query.get(handle_id.entity).is_ok() {
-                (spawner.delete)(&mut commands, handle_id.entity);
-            }
-        }
-    }
+fn delete_unreferenced_components(world: &mut World) {
+    world.flush_ref_comps();
 }
 
 // *****************************************************************************************
@@ -286,15 +978,56 @@ pub trait RefCompExt {
         entity: Entity,
         edit_fn: Option<EditFn<T>>,
     ) -> RefCompHandle<T>;
+    /// Like [`RefCompExt::insert_ref_comp_from_world`], with an edit function that may capture
+    /// values.
+    fn insert_ref_comp_from_world_with_edit<T: Component + FromWorld>(
+        &mut self,
+        entity: Entity,
+        edit_fn: impl FnOnce(&mut World, Entity, &mut T) + Send + 'static,
+    ) -> RefCompHandle<T>;
     fn insert_ref_comp<T: Component>(
         &mut self,
         entity: Entity,
-        insert_fn: InsertFn<T>,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
         edit_fn: Option<EditFn<T>>,
     ) -> RefCompHandle<T>;
+    /// Like [`RefCompExt::insert_ref_comp`], with an edit function that may capture values.
+    fn insert_ref_comp_with_edit<T: Component>(
+        &mut self,
+        entity: Entity,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
+        edit_fn: impl FnOnce(&mut World, Entity, &mut T) + Send + 'static,
+    ) -> RefCompHandle<T>;
+    /// Inserts a reference-counted bundle. See [`RefCompServer::insert_ref_bundle`].
+    fn insert_ref_bundle<B: Bundle>(
+        &mut self,
+        entity: Entity,
+        insert_fn: impl FnOnce(&mut World, Entity) -> B + Send + 'static,
+        edit_fn: Option<EditFn<B>>,
+    ) -> RefCompHandle<B>;
+    /// Like [`RefCompExt::insert_ref_bundle`], with an edit function that may capture values.
+    fn insert_ref_bundle_with_edit<B: Bundle>(
+        &mut self,
+        entity: Entity,
+        insert_fn: impl FnOnce(&mut World, Entity) -> B + Send + 'static,
+        edit_fn: impl FnOnce(&mut World, Entity, &mut B) + Send + 'static,
+    ) -> RefCompHandle<B>;
+    /// Spawns a new entity with `bundle` that is despawned once the last strong handle to it
+    /// is dropped.
+    fn spawn_ref(&mut self, bundle: impl Bundle) -> RefCompHandle<RefEntity>;
+    /// Applies all pending reference changes and releases unreferenced components right away,
+    /// without running the [`RefCompPlugin`]'s system. [`ReleasePolicy`] delays still apply, use
+    /// [`RefCompServer::sweep`] to skip them.
+    fn flush_ref_comps(&mut self) -> RefCompReleaseReport;
 }
 
 impl RefCompExt for World {
+    fn flush_ref_comps(&mut self) -> RefCompReleaseReport {
+        self.resource_scope(|world, mut server: Mut<RefCompServer>| {
+            server.release_unreferenced(world)
+        })
+    }
+
     fn insert_ref_comp_from_world<T: Component + FromWorld>(
         &mut self,
         entity: Entity,
@@ -305,25 +1038,311 @@ impl RefCompExt for World {
         })
     }
 
+    fn insert_ref_comp_from_world_with_edit<T: Component + FromWorld>(
+        &mut self,
+        entity: Entity,
+        edit_fn: impl FnOnce(&mut World, Entity, &mut T) + Send + 'static,
+    ) -> RefCompHandle<T> {
+        self.resource_scope(|world, mut ref_comp_server: Mut<RefCompServer>| {
+            ref_comp_server.inner_insert_ref_comp_from_world::<T>(world, entity, Some(edit_fn))
+        })
+    }
+
     fn insert_ref_comp<T: Component>(
         &mut self,
         entity: Entity,
-        insert_fn: InsertFn<T>,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
         edit_fn: Option<EditFn<T>>,
     ) -> RefCompHandle<T> {
         self.resource_scope(|world, mut ref_comp_server: Mut<RefCompServer>| {
             ref_comp_server.inner_insert_ref_comp::<T>(world, entity, insert_fn, edit_fn)
         })
     }
+
+    fn insert_ref_comp_with_edit<T: Component>(
+        &mut self,
+        entity: Entity,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
+        edit_fn: impl FnOnce(&mut World, Entity, &mut T) + Send + 'static,
+    ) -> RefCompHandle<T> {
+        self.resource_scope(|world, mut ref_comp_server: Mut<RefCompServer>| {
+            ref_comp_server.inner_insert_ref_comp::<T>(world, entity, insert_fn, Some(edit_fn))
+        })
+    }
+
+    fn insert_ref_bundle<B: Bundle>(
+        &mut self,
+        entity: Entity,
+        insert_fn: impl FnOnce(&mut World, Entity) -> B + Send + 'static,
+        edit_fn: Option<EditFn<B>>,
+    ) -> RefCompHandle<B> {
+        self.resource_scope(|world, mut ref_comp_server: Mut<RefCompServer>| {
+            ref_comp_server.inner_insert_ref_comp::<B>(world, entity, insert_fn, edit_fn)
+        })
+    }
+
+    fn insert_ref_bundle_with_edit<B: Bundle>(
+        &mut self,
+        entity: Entity,
+        insert_fn: impl FnOnce(&mut World, Entity) -> B + Send + 'static,
+        edit_fn: impl FnOnce(&mut World, Entity, &mut B) + Send + 'static,
+    ) -> RefCompHandle<B> {
+        self.resource_scope(|world, mut ref_comp_server: Mut<RefCompServer>| {
+            ref_comp_server.inner_insert_ref_comp::<B>(world, entity, insert_fn, Some(edit_fn))
+        })
+    }
+
+    fn spawn_ref(&mut self, bundle: impl Bundle) -> RefCompHandle<RefEntity> {
+        let entity = self.spawn(bundle).id();
+        self.resource_scope(|world, mut ref_comp_server: Mut<RefCompServer>| {
+            ref_comp_server.set_entity_despawn_policy(entity, DespawnPolicy::Despawn);
+            ref_comp_server.inner_insert_ref_comp_from_world::<RefEntity>(
+                world,
+                entity,
+                None::<EditFn<_>>,
+            )
+        })
+    }
+}
+
+/// Reference-counted inserts through [`EntityCommands`].
+pub trait RefCompEntityCommandsExt {
+    /// Queues inserting `T` on this entity through the same command path as
+    /// [`RefCompServer::insert_ref_comp`].
+    fn insert_ref<T: Component>(
+        &mut self,
+        server: &RefCompServer,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
+        edit_fn: Option<EditFn<T>>,
+    ) -> RefCompHandle<T>;
+    /// Like [`RefCompEntityCommandsExt::insert_ref`], with an edit function that may capture
+    /// values.
+    fn insert_ref_with_edit<T: Component>(
+        &mut self,
+        server: &RefCompServer,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
+        edit_fn: impl FnOnce(&mut World, Entity, &mut T) + Send + 'static,
+    ) -> RefCompHandle<T>;
+    /// Queues inserting `T` on this entity through the same command path as
+    /// [`RefCompServer::insert_ref_comp_fw`].
+    fn insert_ref_fw<T: Component + FromWorld>(
+        &mut self,
+        server: &RefCompServer,
+        edit_fn: Option<EditFn<T>>,
+    ) -> RefCompHandle<T>;
+    /// Like [`RefCompEntityCommandsExt::insert_ref_fw`], with an edit function that may capture
+    /// values.
+    fn insert_ref_fw_with_edit<T: Component + FromWorld>(
+        &mut self,
+        server: &RefCompServer,
+        edit_fn: impl FnOnce(&mut World, Entity, &mut T) + Send + 'static,
+    ) -> RefCompHandle<T>;
+}
+
+impl<'w, 's, 'a> RefCompEntityCommandsExt for EntityCommands<'w, 's, 'a> {
+    fn insert_ref<T: Component>(
+        &mut self,
+        server: &RefCompServer,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
+        edit_fn: Option<EditFn<T>>,
+    ) -> RefCompHandle<T> {
+        let entity = self.id();
+        server.insert_ref_comp(self.commands(), entity, insert_fn, edit_fn)
+    }
+
+    fn insert_ref_with_edit<T: Component>(
+        &mut self,
+        server: &RefCompServer,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
+        edit_fn: impl FnOnce(&mut World, Entity, &mut T) + Send + 'static,
+    ) -> RefCompHandle<T> {
+        let entity = self.id();
+        server.insert_ref_comp_with_edit(self.commands(), entity, insert_fn, edit_fn)
+    }
+
+    fn insert_ref_fw<T: Component + FromWorld>(
+        &mut self,
+        server: &RefCompServer,
+        edit_fn: Option<EditFn<T>>,
+    ) -> RefCompHandle<T> {
+        self.insert_ref(server, from_world::<T>, edit_fn)
+    }
+
+    fn insert_ref_fw_with_edit<T: Component + FromWorld>(
+        &mut self,
+        server: &RefCompServer,
+        edit_fn: impl FnOnce(&mut World, Entity, &mut T) + Send + 'static,
+    ) -> RefCompHandle<T> {
+        self.insert_ref_with_edit(server, from_world::<T>, edit_fn)
+    }
+}
+
+/// Reference-counted inserts through [`EntityMut`].
+pub trait RefCompEntityMutExt {
+    /// Inserts `T` on this entity. See [`RefCompExt::insert_ref_comp`].
+    fn insert_ref<T: Component>(
+        &mut self,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
+        edit_fn: Option<EditFn<T>>,
+    ) -> RefCompHandle<T>;
+    /// Inserts `T` on this entity. See [`RefCompExt::insert_ref_comp_with_edit`].
+    fn insert_ref_with_edit<T: Component>(
+        &mut self,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
+        edit_fn: impl FnOnce(&mut World, Entity, &mut T) + Send + 'static,
+    ) -> RefCompHandle<T>;
+    /// Inserts `T` on this entity. See [`RefCompExt::insert_ref_comp_from_world`].
+    fn insert_ref_from_world<T: Component + FromWorld>(
+        &mut self,
+        edit_fn: Option<EditFn<T>>,
+    ) -> RefCompHandle<T>;
+    /// Inserts `T` on this entity. See [`RefCompExt::insert_ref_comp_from_world_with_edit`].
+    fn insert_ref_from_world_with_edit<T: Component + FromWorld>(
+        &mut self,
+        edit_fn: impl FnOnce(&mut World, Entity, &mut T) + Send + 'static,
+    ) -> RefCompHandle<T>;
+}
+
+impl<'w> RefCompEntityMutExt for EntityMut<'w> {
+    fn insert_ref<T: Component>(
+        &mut self,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
+        edit_fn: Option<EditFn<T>>,
+    ) -> RefCompHandle<T> {
+        let entity = self.id();
+        self.world_scope(|world| world.insert_ref_comp(entity, insert_fn, edit_fn))
+    }
+
+    fn insert_ref_with_edit<T: Component>(
+        &mut self,
+        insert_fn: impl FnOnce(&mut World, Entity) -> T + Send + 'static,
+        edit_fn: impl FnOnce(&mut World, Entity, &mut T) + Send + 'static,
+    ) -> RefCompHandle<T> {
+        let entity = self.id();
+        self.world_scope(|world| world.insert_ref_comp_with_edit(entity, insert_fn, edit_fn))
+    }
+
+    fn insert_ref_from_world<T: Component + FromWorld>(
+        &mut self,
+        edit_fn: Option<EditFn<T>>,
+    ) -> RefCompHandle<T> {
+        let entity = self.id();
+        self.world_scope(|world| world.insert_ref_comp_from_world(entity, edit_fn))
+    }
+
+    fn insert_ref_from_world_with_edit<T: Component + FromWorld>(
+        &mut self,
+        edit_fn: impl FnOnce(&mut World, Entity, &mut T) + Send + 'static,
+    ) -> RefCompHandle<T> {
+        let entity = self.id();
+        self.world_scope(|world| world.insert_ref_comp_from_world_with_edit(entity, edit_fn))
+    }
 }
+
+// *****************************************************************************************
+// Events
+// *****************************************************************************************
+/// Sent when the [`RefCompServer`] inserts a component because none was present yet.
+#[derive(Debug, Clone)]
+pub struct RefCompInserted {
+    pub id: RefCompHandleId,
+}
+
+/// Sent when the [`RefCompServer`] runs an [`EditFn`] on a component that was already present.
+#[derive(Debug, Clone)]
+pub struct RefCompEdited {
+    pub id: RefCompHandleId,
+}
+
+/// Sent when the last strong handle to a component was dropped and the [`RefCompServer`] removed
+/// it.
+#[derive(Debug, Clone)]
+pub struct RefCompReleased {
+    pub id: RefCompHandleId,
+}
+
+/// Sent when a component managed by the [`RefCompServer`] was removed by something else and its
+/// handles were invalidated. See [`RemovalPolicy`].
+#[derive(Debug, Clone)]
+pub struct RefCompInvalidated {
+    pub id: RefCompHandleId,
+}
+
+/// Sent when the [`RefCompServer`] receives a reference change it cannot apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefCountError {
+    /// A strong handle was dropped for an id the server holds no references to, for example
+    /// because the handle was created by a different server.
+    UnmatchedDecrement(RefCompHandleId),
+}
+
+impl std::error::Error for RefCountError {}
+
+impl std::fmt::Display for RefCountError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefCountError::UnmatchedDecrement(id) => write!(
+                f,
+                "A strong handle to {id:?} was dropped but the RefCompServer holds no references to it."
+            ),
+        }
+    }
+}
+
 // *****************************************************************************************
 // Structs
 // *****************************************************************************************
+/// Marks an entity spawned with [`RefCompServer::spawn_ref`]. Handles to it keep the whole
+/// entity alive.
+#[derive(Component, Debug, Default, Clone, Copy)]
+pub struct RefEntity;
+
+/// What a release pass of the [`RefCompServer`] changed in the world.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RefCompReleaseReport {
+    /// Components removed because no strong handles point at them anymore.
+    pub released: Vec<RefCompHandleId>,
+    /// Ids whose component or entity disappeared while strong handles to them were alive.
+    pub invalidated: Vec<RefCompHandleId>,
+    /// Entities despawned by their [`DespawnPolicy`] after their last component was released.
+    pub despawned: Vec<Entity>,
+}
+
+impl RefCompReleaseReport {
+    /// Returns true if the release pass changed nothing.
+    pub fn is_empty(&self) -> bool {
+        self.released.is_empty() && self.invalidated.is_empty() && self.despawned.is_empty()
+    }
+}
+
+#[derive(Default)]
 enum RefCompHandleType {
+    #[default]
     Weak,
     Strong(Sender<RefChange>),
 }
 
+impl RefCompHandleType {
+    /// Maps the entity of `id`. A Strong handle moves its count from the old id to the new one,
+    /// and the server moves its bookkeeping once no strong handle points at the old id.
+    fn map_id(
+        &self,
+        id: &mut RefCompHandleId,
+        entity_map: &EntityMap,
+    ) -> Result<(), MapEntitiesError> {
+        let old_id = *id;
+        id.map_entities(entity_map)?;
+        if let RefCompHandleType::Strong(sender) = self {
+            if *id != old_id {
+                // ignore send errors because this means the channel is shut down / the game has
+                // stopped
+                let _ = sender.send(RefChange::Remap(old_id, *id));
+            }
+        }
+        Ok(())
+    }
+}
+
 impl core::fmt::Debug for RefCompHandleType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -333,46 +1352,60 @@ impl core::fmt::Debug for RefCompHandleType {
     }
 }
 
-impl Default for RefCompHandleType {
-    fn default() -> Self {
-        Self::Weak
-    }
-}
 #[derive(
-    Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize, Reflect, FromReflect,
+    Debug,
+    Clone,
+    Copy,
+    Eq,
+    PartialEq,
+    Hash,
+    Ord,
+    PartialOrd,
+    Serialize,
+    Deserialize,
+    Reflect,
+    FromReflect,
 )]
 #[reflect_value(Serialize, Deserialize, PartialEq, Hash)]
 pub struct RefCompHandleId {
+    #[serde(with = "handle_serde::entity_bits")]
     pub entity: Entity,
-    pub type_id: String,
+    #[serde(rename = "component")]
+    pub type_id: RefCompTypeId,
+}
+
+impl MapEntities for RefCompHandleId {
+    fn map_entities(&mut self, entity_map: &EntityMap) -> Result<(), MapEntitiesError> {
+        self.entity = entity_map.get(self.entity)?;
+        Ok(())
+    }
 }
 
 impl RefCompHandleId {
     #[inline]
-    pub fn default_handle<T: Component>() -> Self {
+    pub fn default_handle<T: Bundle>() -> Self {
         RefCompHandleId {
             entity: Entity::from_raw(u32::MAX),
-            type_id: "".to_string(),
+            type_id: RefCompTypeId::of::<T>(),
         }
     }
 
     #[inline]
-    pub fn new<T: Component>(entity: Entity) -> Self {
+    pub fn new<T: Bundle>(entity: Entity) -> Self {
         RefCompHandleId {
             entity,
-            type_id: type_name::<T>().to_string(),
+            type_id: RefCompTypeId::of::<T>(),
         }
     }
 }
 
-impl<T: Component> RefCompHandle<T> {
+impl<T: Bundle> RefCompHandle<T> {
     fn strong(id: RefCompHandleId, ref_change_sender: Sender<RefChange>) -> Self {
-        ref_change_sender
-            .send(RefChange::Increment(id.clone()))
-            .unwrap();
+        ref_change_sender.send(RefChange::Increment(id)).unwrap();
         Self {
             id,
             handle_type: RefCompHandleType::Strong(ref_change_sender),
+            strong: true,
             marker: PhantomData,
         }
     }
@@ -382,17 +1415,14 @@ impl<T: Component> RefCompHandle<T> {
         Self {
             id,
             handle_type: RefCompHandleType::Weak,
+            strong: false,
             marker: PhantomData,
         }
     }
 
     /// Get a copy of this handle as a Weak handle
-    pub fn as_weak<U: Component>(&self) -> RefCompHandle<U> {
-        RefCompHandle {
-            id: self.id.clone(),
-            handle_type: RefCompHandleType::Weak,
-            marker: PhantomData,
-        }
+    pub fn as_weak<U: Bundle>(&self) -> RefCompHandle<U> {
+        RefCompHandle::weak(self.id)
     }
 
     pub fn is_weak(&self) -> bool {
@@ -403,6 +1433,28 @@ impl<T: Component> RefCompHandle<T> {
         matches!(self.handle_type, RefCompHandleType::Strong(_))
     }
 
+    /// Returns false if the entity this handle points at has been despawned, or its component
+    /// invalidated.
+    ///
+    /// See [`RefCompServer::is_alive`].
+    pub fn is_alive(&self, world: &World) -> bool {
+        match world.get_resource::<RefCompServer>() {
+            Some(server) => server.is_alive(world, &self.id),
+            None => world.entities().contains(self.id.entity),
+        }
+    }
+
+    /// Get a strong handle to the same component, or `None` if it has already been released or
+    /// its entity despawned.
+    ///
+    /// Unlike [`RefCompHandle::make_strong`], this never starts counting a component the server
+    /// is no longer tracking.
+    pub fn try_upgrade(&self, server: &RefCompServer) -> Option<RefCompHandle<T>> {
+        server
+            .is_tracked(&self.id)
+            .then(|| server.get_handle(self.id))
+    }
+
     /// Makes this handle Strong if it wasn't already.
     ///
     /// This method requires the corresponding [Assets](crate::Assets) collection
@@ -411,36 +1463,37 @@ impl<T: Component> RefCompHandle<T> {
             return;
         }
         let sender = server.channel.sender.clone();
-        sender.send(RefChange::Increment(self.id.clone())).unwrap();
+        sender.send(RefChange::Increment(self.id)).unwrap();
         self.handle_type = RefCompHandleType::Strong(sender);
+        self.strong = true;
     }
 
     #[inline]
     pub fn clone_weak(&self) -> Self {
-        RefCompHandle::weak(self.id.clone())
+        RefCompHandle::weak(self.id)
     }
 
     pub fn clone_untyped(&self) -> RefCompHandleUntyped {
         match &self.handle_type {
             RefCompHandleType::Strong(sender) => {
-                RefCompHandleUntyped::strong(self.id.clone(), sender.clone())
+                RefCompHandleUntyped::strong(self.id, sender.clone())
             }
-            RefCompHandleType::Weak => RefCompHandleUntyped::weak(self.id.clone()),
+            RefCompHandleType::Weak => RefCompHandleUntyped::weak(self.id),
         }
     }
 
     pub fn clone_weak_untyped(&self) -> RefCompHandleUntyped {
-        RefCompHandleUntyped::weak(self.id.clone())
+        RefCompHandleUntyped::weak(self.id)
     }
 }
 
-impl<T: Component> Drop for RefCompHandle<T> {
+impl<T: Bundle> Drop for RefCompHandle<T> {
     fn drop(&mut self) {
         match self.handle_type {
             RefCompHandleType::Strong(ref sender) => {
                 // ignore send errors because this means the channel is shut down / the game has
                 // stopped
-                let _ = sender.send(RefChange::Decrement(self.id.clone()));
+                let _ = sender.send(RefChange::Decrement(self.id));
             }
             RefCompHandleType::Weak => {}
         }
@@ -448,27 +1501,36 @@ impl<T: Component> Drop for RefCompHandle<T> {
 }
 
 #[derive(Component, Reflect, FromReflect)]
-#[reflect(Component)]
+#[reflect(Component, MapEntities)]
 pub struct RefCompHandle<T>
 where
-    T: Component,
+    T: Bundle,
 {
     /// The ID of the asset as contained within its respective [Assets](crate::Assets) collection
     pub id: RefCompHandleId,
     #[reflect(ignore)]
     handle_type: RefCompHandleType,
+    /// Mirrors `handle_type` for reflection, so that a handle saved in a scene as Strong is made
+    /// Strong again after loading. See [`RefCompAppExt::register_ref_handle`].
+    strong: bool,
     #[reflect(ignore)]
     // NOTE: PhantomData<fn() -> T> gives this safe Send/Sync impls
     marker: PhantomData<fn() -> T>,
 }
 
-impl<T: Component> Default for RefCompHandle<T> {
+impl<T: Bundle> MapEntities for RefCompHandle<T> {
+    fn map_entities(&mut self, entity_map: &EntityMap) -> Result<(), MapEntitiesError> {
+        self.handle_type.map_id(&mut self.id, entity_map)
+    }
+}
+
+impl<T: Bundle> Default for RefCompHandle<T> {
     fn default() -> Self {
         RefCompHandle::weak(RefCompHandleId::default_handle::<T>())
     }
 }
 
-impl<T: Component> std::fmt::Debug for RefCompHandle<T> {
+impl<T: Bundle> std::fmt::Debug for RefCompHandle<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
         let name = std::any::type_name::<T>().split("::").last().unwrap();
         write!(
@@ -479,13 +1541,15 @@ impl<T: Component> std::fmt::Debug for RefCompHandle<T> {
     }
 }
 
-impl<T: Component> Clone for RefCompHandle<T> {
+impl<T: Bundle> Clone for RefCompHandle<T> {
     fn clone(&self) -> Self {
         match self.handle_type {
-            RefCompHandleType::Strong(ref sender) => {
-                RefCompHandle::strong(self.id.clone(), sender.clone())
+            RefCompHandleType::Strong(ref sender) => RefCompHandle::strong(self.id, sender.clone()),
+            RefCompHandleType::Weak => {
+                let mut handle = RefCompHandle::weak(self.id);
+                handle.strong = self.strong;
+                handle
             }
-            RefCompHandleType::Weak => RefCompHandle::weak(self.id.clone()),
         }
     }
 }
@@ -497,7 +1561,7 @@ pub struct RefCompHandleUntyped {
 }
 
 impl RefCompHandleUntyped {
-    pub fn weak_from_entity<T: Component>(entity: Entity) -> Self {
+    pub fn weak_from_entity<T: Bundle>(entity: Entity) -> Self {
         Self {
             id: RefCompHandleId::new::<T>(entity),
             handle_type: RefCompHandleType::Weak,
@@ -505,9 +1569,7 @@ impl RefCompHandleUntyped {
     }
 
     fn strong(id: RefCompHandleId, ref_change_sender: Sender<RefChange>) -> Self {
-        ref_change_sender
-            .send(RefChange::Increment(id.clone()))
-            .unwrap();
+        ref_change_sender.send(RefChange::Increment(id)).unwrap();
         Self {
             id,
             handle_type: RefCompHandleType::Strong(ref_change_sender),
@@ -522,7 +1584,7 @@ impl RefCompHandleUntyped {
     }
 
     pub fn clone_weak(&self) -> RefCompHandleUntyped {
-        RefCompHandleUntyped::weak(self.id.clone())
+        RefCompHandleUntyped::weak(self.id)
     }
 
     pub fn is_weak(&self) -> bool {
@@ -533,31 +1595,50 @@ impl RefCompHandleUntyped {
         matches!(self.handle_type, RefCompHandleType::Strong(_))
     }
 
+    /// Returns false if the entity this handle points at has been despawned, or its component
+    /// invalidated.
+    ///
+    /// See [`RefCompServer::is_alive`].
+    pub fn is_alive(&self, world: &World) -> bool {
+        match world.get_resource::<RefCompServer>() {
+            Some(server) => server.is_alive(world, &self.id),
+            None => world.entities().contains(self.id.entity),
+        }
+    }
+
     /// Convert this handle into a typed [Handle].
     ///
     /// The new handle will maintain the Strong or Weak status of the current handle.
-    pub fn typed<T: Component>(mut self) -> RefCompHandle<T> {
+    pub fn typed<T: Bundle>(mut self) -> RefCompHandle<T> {
         let handle_type = match &self.handle_type {
             RefCompHandleType::Strong(sender) => RefCompHandleType::Strong(sender.clone()),
             RefCompHandleType::Weak => RefCompHandleType::Weak,
         };
         // ensure we don't send the RefChange event when "self" is dropped
         self.handle_type = RefCompHandleType::Weak;
+        let strong = matches!(handle_type, RefCompHandleType::Strong(_));
         RefCompHandle {
             handle_type,
-            id: self.id.clone(),
-            marker: PhantomData::default(),
+            id: self.id,
+            strong,
+            marker: PhantomData,
         }
     }
 }
 
+impl MapEntities for RefCompHandleUntyped {
+    fn map_entities(&mut self, entity_map: &EntityMap) -> Result<(), MapEntitiesError> {
+        self.handle_type.map_id(&mut self.id, entity_map)
+    }
+}
+
 impl Drop for RefCompHandleUntyped {
     fn drop(&mut self) {
         match self.handle_type {
             RefCompHandleType::Strong(ref sender) => {
                 // ignore send errors because this means the channel is shut down / the game has
                 // stopped
-                let _ = sender.send(RefChange::Decrement(self.id.clone()));
+                let _ = sender.send(RefChange::Decrement(self.id));
             }
             RefCompHandleType::Weak => {}
         }
@@ -567,6 +1648,29 @@ impl Drop for RefCompHandleUntyped {
 enum RefChange {
     Increment(RefCompHandleId),
     Decrement(RefCompHandleId),
+    /// A strong handle was remapped from the first id to the second.
+    Remap(RefCompHandleId, RefCompHandleId),
+}
+
+/// Reference changes received from the channel, in order, along with their net effect per id.
+#[derive(Default)]
+struct ReceivedRefChanges {
+    changes: Vec<RefChange>,
+    deltas: HashMap<RefCompHandleId, isize>,
+}
+
+impl ReceivedRefChanges {
+    fn push(&mut self, ref_change: RefChange) {
+        match ref_change {
+            RefChange::Increment(id) => *self.deltas.entry(id).or_insert(0) += 1,
+            RefChange::Decrement(id) => *self.deltas.entry(id).or_insert(0) -= 1,
+            RefChange::Remap(old_id, new_id) => {
+                *self.deltas.entry(old_id).or_insert(0) -= 1;
+                *self.deltas.entry(new_id).or_insert(0) += 1;
+            }
+        }
+        self.changes.push(ref_change);
+    }
 }
 
 #[derive(Clone)]
@@ -583,11 +1687,172 @@ impl Default for RefChangeChannel {
 }
 
 struct RefComponentSpawner {
-    delete: fn(&mut Commands, Entity),
+    delete: DeleteFn,
+    contains: ContainsFn,
+    removal_policy: RemovalPolicy,
+    /// Overrides the server's default [`ReleasePolicy`] for this type.
+    release_policy: Option<ReleasePolicy>,
+    despawn_policy: DespawnPolicy,
+    /// Inserts the component when no other insert function is available.
+    default_insert: Option<DefaultInsertFn>,
+}
+
+/// Whether the [`RefCompServer`] despawns an entity once it no longer holds any component the
+/// server manages. Ordered from the weakest to the strongest policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DespawnPolicy {
+    /// Leave the entity alive.
+    #[default]
+    Keep,
+    /// Despawn the entity.
+    Despawn,
+    /// Despawn the entity and all of its children.
+    DespawnRecursive,
+}
+
+/// When the [`RefCompServer`] removes a component after its last strong handle was dropped.
+///
+/// Getting a new strong handle to the component before then cancels the release.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ReleasePolicy {
+    /// Remove the component the next time unreferenced components are released.
+    #[default]
+    Immediate,
+    /// Wait this many more frames before removing the component. Frames are read from bevy's
+    /// [`FrameCount`], which the default plugins add. Without it, each release pass counts as a
+    /// frame, including manual ones through [`RefCompExt::flush_ref_comps`].
+    Frames(u32),
+    /// Wait until this much [`Time`] has passed before removing the component. Measured in real
+    /// time if the world has no [`Time`] resource.
+    Duration(Duration),
+    /// Keep the component until [`RefCompServer::sweep`] is called.
+    Never,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PendingRelease {
+    frame: u32,
+    elapsed: Duration,
+}
+
+/// What the [`RefCompServer`] does when a component it manages is removed by something else
+/// while strong handles to it are still alive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum RemovalPolicy {
+    /// Insert the component again through the insert function its type was registered with,
+    /// see [`RefCompRegistration::with_insert_fn`]. Components inserted from a reflected value
+    /// are reinserted with that value. Types without an insert function can't use this policy.
+    Reinsert,
+    /// Stop tracking the component, make its handles report that they are no longer alive and
+    /// send a [`RefCompInvalidated`] event.
+    #[default]
+    Invalidate,
+    /// Panic in debug builds. Behaves like [`RemovalPolicy::Invalidate`] in release builds.
+    Panic,
 }
 // *****************************************************************************************
 // Functions
 // *****************************************************************************************
-fn delete_component<T: Component>(commands: &mut Commands, entity: Entity) {
-    commands.entity(entity).remove::<T>();
+fn queue_entity_despawn_policy(commands: &mut Commands, entity: Entity, policy: DespawnPolicy) {
+    commands.add(move |world: &mut World| {
+        if let Some(mut server) = world.get_resource_mut::<RefCompServer>() {
+            server.set_entity_despawn_policy(entity, policy);
+        }
+    });
+}
+
+/// Panics if `T` would use [`RemovalPolicy::Reinsert`] without an insert function to reinsert
+/// it with, since its handles could then outlive the component.
+fn assert_reinsertable<T>(policy: RemovalPolicy, has_insert_fn: bool) {
+    assert!(
+        policy != RemovalPolicy::Reinsert || has_insert_fn,
+        "`{}` uses RemovalPolicy::Reinsert but has no insert function to reinsert it with. \
+        Register one with `RefCompRegistration::with_insert_fn`.",
+        std::any::type_name::<T>()
+    );
+}
+
+/// Makes `RefCompHandle<T>` components that were saved as Strong, Strong again. Each call only
+/// looks at the handle components added since the previous one.
+fn reattach_handles<T: Bundle>() -> HandleReattacher {
+    let mut added: Option<SystemState<AddedHandles<T>>> = None;
+    Box::new(move |world: &mut World, sender: &Sender<RefChange>| {
+        let added = added.get_or_insert_with(|| SystemState::new(world));
+        for mut handle in added.get_mut(world).iter_mut() {
+            // Only take the handle mutably when it changes, to keep change detection meaningful.
+            if handle.strong && handle.is_weak() {
+                let _ = sender.send(RefChange::Increment(handle.id));
+                handle.handle_type = RefCompHandleType::Strong(sender.clone());
+            }
+        }
+    })
+}
+
+type AddedHandles<T> =
+    Query<'static, 'static, &'static mut RefCompHandle<T>, Added<RefCompHandle<T>>>;
+
+/// Sends `event` if the world has its [`Events`] resource, which the [`RefCompPlugin`] adds.
+/// Worlds used without the plugin, see [`RefCompExt::flush_ref_comps`], may not have it.
+fn send_event<E: Event>(world: &mut World, event: E) {
+    if let Some(mut events) = world.get_resource_mut::<Events<E>>() {
+        events.send(event);
+    }
+}
+
+/// An insert function that creates the component with [`FromWorld`].
+fn from_world<T: FromWorld>(world: &mut World, _entity: Entity) -> T {
+    T::from_world(world)
+}
+
+fn delete_component<T: Bundle>(world: &mut World, entity: Entity) {
+    world.entity_mut(entity).remove::<T>();
+}
+
+/// Returns true if `entity` has every component of the bundle `B`.
+fn contains_bundle<B: Bundle>(world: &World, entity: Entity) -> bool {
+    let Some(entity) = world.get_entity(entity) else {
+        return false;
+    };
+    if let Some(component_id) = world.components().get_id(TypeId::of::<B>()) {
+        return entity.contains_id(component_id);
+    }
+    world
+        .bundles()
+        .get_id(TypeId::of::<B>())
+        .and_then(|bundle_id| world.bundles().get(bundle_id))
+        .is_some_and(|bundle| {
+            bundle
+                .components()
+                .iter()
+                .all(|component_id| entity.contains_id(*component_id))
+        })
+}
+
+/// Runs `edit_fn` on the component if `entity` already has one, otherwise inserts the
+/// component built by `insert_fn`. Returns true if the component was inserted.
+fn insert_or_edit<T: Bundle>(
+    world: &mut World,
+    entity: Entity,
+    insert_fn: impl FnOnce(&mut World, Entity) -> T,
+    edit_fn: Option<impl FnOnce(&mut World, Entity, &mut T)>,
+) -> bool {
+    let id = RefCompHandleId::new::<T>(entity);
+    match contains_bundle::<T>(world, entity) {
+        true => {
+            if let Some(edit_fn) = edit_fn {
+                if let Some(mut comp) = world.entity_mut(entity).take::<T>() {
+                    edit_fn(world, entity, &mut comp);
+                    world.entity_mut(entity).insert(comp);
+                    send_event(world, RefCompEdited { id });
+                }
+            }
+            false
+        }
+        false => {
+            let comp = insert_fn(world, entity);
+            world.entity_mut(entity).insert(comp);
+            send_event(world, RefCompInserted { id });
+            true
+        }
+    }
 }
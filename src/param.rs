@@ -0,0 +1,119 @@
+use std::{any::TypeId, fmt};
+
+use bevy::{
+    ecs::{query::QueryEntityError, system::SystemParam},
+    prelude::{Component, Entity, Mut, Query, Res},
+};
+
+use crate::{RefCompHandle, RefCompHandleId, RefCompServer};
+
+/// A [`SystemParam`] that resolves [`RefCompHandle`]s to the components they point at.
+#[derive(SystemParam)]
+pub struct RefComps<'w, 's, T: Component> {
+    query: Query<'w, 's, &'static T>,
+    server: Res<'w, RefCompServer>,
+}
+
+impl<'w, 's, T: Component> RefComps<'w, 's, T> {
+    /// Get the component `handle` points at.
+    pub fn get(&self, handle: &RefCompHandle<T>) -> Result<&T, RefCompError> {
+        check_type::<T>(&handle.id)?;
+        self.query
+            .get(handle.id.entity)
+            .map_err(|err| RefCompError::from_query(handle.id, err))
+    }
+
+    /// Iterate over every component of type `T` the [`RefCompServer`] is tracking, paired with a
+    /// weak handle to it.
+    pub fn iter_handles(&self) -> impl Iterator<Item = (RefCompHandle<T>, &T)> + '_ {
+        iter_handles(&self.server, |entity| self.query.get(entity).ok())
+    }
+}
+
+/// A [`SystemParam`] that resolves [`RefCompHandle`]s to the components they point at, with
+/// mutable access.
+#[derive(SystemParam)]
+pub struct RefCompsMut<'w, 's, T: Component> {
+    query: Query<'w, 's, &'static mut T>,
+    server: Res<'w, RefCompServer>,
+}
+
+impl<'w, 's, T: Component> RefCompsMut<'w, 's, T> {
+    /// Get the component `handle` points at.
+    pub fn get(&self, handle: &RefCompHandle<T>) -> Result<&T, RefCompError> {
+        check_type::<T>(&handle.id)?;
+        self.query
+            .get(handle.id.entity)
+            .map_err(|err| RefCompError::from_query(handle.id, err))
+    }
+
+    /// Get mutable access to the component `handle` points at.
+    pub fn get_mut(&mut self, handle: &RefCompHandle<T>) -> Result<Mut<'_, T>, RefCompError> {
+        check_type::<T>(&handle.id)?;
+        self.query
+            .get_mut(handle.id.entity)
+            .map_err(|err| RefCompError::from_query(handle.id, err))
+    }
+
+    /// Iterate over every component of type `T` the [`RefCompServer`] is tracking, paired with a
+    /// weak handle to it.
+    pub fn iter_handles(&self) -> impl Iterator<Item = (RefCompHandle<T>, &T)> + '_ {
+        iter_handles(&self.server, |entity| self.query.get(entity).ok())
+    }
+}
+
+/// An error that occurs when resolving a [`RefCompHandle`] to its component.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RefCompError {
+    /// The entity the handle points at does not exist.
+    NoSuchEntity(RefCompHandleId),
+    /// The entity the handle points at does not have the component.
+    MissingComponent(RefCompHandleId),
+    /// The handle's id refers to a different component type than the one requested.
+    WrongType(RefCompHandleId),
+}
+
+impl RefCompError {
+    fn from_query(id: RefCompHandleId, err: QueryEntityError) -> Self {
+        match err {
+            QueryEntityError::QueryDoesNotMatch(_) => RefCompError::MissingComponent(id),
+            _ => RefCompError::NoSuchEntity(id),
+        }
+    }
+}
+
+impl std::error::Error for RefCompError {}
+
+impl fmt::Display for RefCompError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RefCompError::NoSuchEntity(id) => {
+                write!(f, "The entity of {id:?} does not exist.")
+            }
+            RefCompError::MissingComponent(id) => {
+                write!(f, "The entity of {id:?} does not have the component.")
+            }
+            RefCompError::WrongType(id) => {
+                write!(f, "{id:?} does not refer to the requested component type.")
+            }
+        }
+    }
+}
+
+fn check_type<T: Component>(id: &RefCompHandleId) -> Result<(), RefCompError> {
+    match id.type_id.type_id() == TypeId::of::<T>() {
+        true => Ok(()),
+        false => Err(RefCompError::WrongType(*id)),
+    }
+}
+
+fn iter_handles<'a, T: Component>(
+    server: &'a RefCompServer,
+    get: impl Fn(Entity) -> Option<&'a T> + 'a,
+) -> impl Iterator<Item = (RefCompHandle<T>, &'a T)> + 'a {
+    server
+        .ref_counts
+        .keys()
+        .filter(|id| id.type_id.type_id() == TypeId::of::<T>())
+        .filter_map(move |id| get(id.entity).map(|comp| (RefCompHandle::weak(*id), comp)))
+}
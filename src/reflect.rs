@@ -0,0 +1,111 @@
+use std::fmt;
+
+use bevy::{
+    ecs::reflect::ReflectComponent,
+    prelude::{AppTypeRegistry, Entity, World},
+    reflect::Reflect,
+};
+
+use crate::{
+    send_event, RefCompHandleId, RefCompHandleUntyped, RefCompInserted, RefCompServer,
+    RefCompTypeId,
+};
+
+impl RefCompServer {
+    /// Inserts `component` on `entity` and returns a strong handle to it. A component the entity
+    /// already has is left untouched, like the typed insert paths do without an edit function.
+    ///
+    /// The component type is looked up by its reflected type name in the [`AppTypeRegistry`],
+    /// and must be registered with `#[reflect(Component)]`. `component` may be a dynamic value,
+    /// such as one produced by a reflect deserializer.
+    pub fn insert_ref_comp_reflect(
+        &mut self,
+        world: &mut World,
+        entity: Entity,
+        component: Box<dyn Reflect>,
+    ) -> Result<RefCompHandleUntyped, RefCompReflectError> {
+        let type_name = component.type_name();
+        let (type_id, reflect_component) = {
+            let registry = world
+                .get_resource::<AppTypeRegistry>()
+                .ok_or(RefCompReflectError::MissingTypeRegistry)?
+                .read();
+            let registration = registry
+                .get_with_name(type_name)
+                .ok_or_else(|| RefCompReflectError::UnregisteredType(type_name.to_string()))?;
+            let reflect_component = registration
+                .data::<ReflectComponent>()
+                .cloned()
+                .ok_or_else(|| {
+                    RefCompReflectError::MissingReflectComponent(type_name.to_string())
+                })?;
+            let type_id =
+                RefCompTypeId::from_type_id(registration.type_id(), registration.type_name());
+            (type_id, reflect_component)
+        };
+        if world.get_entity(entity).is_none() {
+            return Err(RefCompReflectError::NoSuchEntity(entity));
+        }
+
+        let delete = reflect_component.clone();
+        let contains = reflect_component.clone();
+        self.register_spawner_untyped(
+            type_id,
+            move |world: &mut World, entity| {
+                if let Some(mut entity) = world.get_entity_mut(entity) {
+                    delete.remove(&mut entity);
+                }
+            },
+            move |world: &World, entity| {
+                world
+                    .get_entity(entity)
+                    .is_some_and(|entity| contains.contains(entity))
+            },
+        );
+
+        let id = RefCompHandleId { entity, type_id };
+        let handle = RefCompHandleUntyped::strong(id, self.channel.sender.clone());
+        if !reflect_component.contains(world.entity(entity)) {
+            reflect_component.insert(&mut world.entity_mut(entity), &*component);
+            send_event(world, RefCompInserted { id });
+            self.track_insert_untyped(id, move |world: &mut World, entity| {
+                reflect_component.insert(&mut world.entity_mut(entity), &*component);
+            });
+        }
+        Ok(handle)
+    }
+}
+
+/// An error that occurs when inserting a reference-counted component from a reflected value.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RefCompReflectError {
+    /// The world has no [`AppTypeRegistry`].
+    MissingTypeRegistry,
+    /// The value's type is not registered in the [`AppTypeRegistry`].
+    UnregisteredType(String),
+    /// The value's type is registered without `#[reflect(Component)]`.
+    MissingReflectComponent(String),
+    /// The target entity does not exist.
+    NoSuchEntity(Entity),
+}
+
+impl std::error::Error for RefCompReflectError {}
+
+impl fmt::Display for RefCompReflectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RefCompReflectError::MissingTypeRegistry => {
+                write!(f, "The world has no AppTypeRegistry.")
+            }
+            RefCompReflectError::UnregisteredType(name) => {
+                write!(f, "`{name}` is not registered in the AppTypeRegistry.")
+            }
+            RefCompReflectError::MissingReflectComponent(name) => {
+                write!(f, "`{name}` is not registered with #[reflect(Component)].")
+            }
+            RefCompReflectError::NoSuchEntity(entity) => {
+                write!(f, "The entity {entity:?} does not exist.")
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use bevy::prelude::{App, Bundle, Component, Entity, World};
+
+use crate::{
+    assert_reinsertable, reattach_handles, DefaultInsertFn, DespawnPolicy, RefCompBuilder,
+    RefCompHandle, RefCompServer, RefCompTypeId, ReleasePolicy, RemovalPolicy,
+};
+
+/// Describes how the [`RefCompServer`] manages a type.
+/// See [`RefCompAppExt::register_ref_component_with`].
+pub struct RefCompRegistration<T: Bundle> {
+    release_policy: Option<ReleasePolicy>,
+    removal_policy: RemovalPolicy,
+    despawn_policy: DespawnPolicy,
+    insert_fn: Option<DefaultInsertFn>,
+    name: Option<&'static str>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Bundle> Default for RefCompRegistration<T> {
+    fn default() -> Self {
+        RefCompRegistration {
+            release_policy: None,
+            removal_policy: RemovalPolicy::default(),
+            despawn_policy: DespawnPolicy::default(),
+            insert_fn: None,
+            name: None,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Bundle> RefCompRegistration<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Overrides the server's default [`ReleasePolicy`] for this type.
+    pub fn with_release_policy(mut self, release_policy: ReleasePolicy) -> Self {
+        self.release_policy = Some(release_policy);
+        self
+    }
+
+    pub fn with_removal_policy(mut self, removal_policy: RemovalPolicy) -> Self {
+        self.removal_policy = removal_policy;
+        self
+    }
+
+    pub fn with_despawn_policy(mut self, despawn_policy: DespawnPolicy) -> Self {
+        self.despawn_policy = despawn_policy;
+        self
+    }
+
+    /// Sets the stable name handles to this type are serialized with.
+    /// See [`RefCompTypeId::set_name`].
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Sets the function used by [`RefCompServer::insert_ref_comp_default`] and by
+    /// [`RemovalPolicy::Reinsert`].
+    pub fn with_insert_fn(
+        mut self,
+        insert_fn: impl Fn(&mut World, Entity) -> T + Send + Sync + 'static,
+    ) -> Self {
+        self.insert_fn = Some(Arc::new(move |world: &mut World, entity| {
+            let comp = insert_fn(world, entity);
+            world.entity_mut(entity).insert(comp);
+        }));
+        self
+    }
+}
+
+impl RefCompServer {
+    /// Registers `T` so that handles to it can be released even if the server has not inserted
+    /// it yet, for example after deserializing them. Replaces any earlier registration of `T`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the registration uses [`RemovalPolicy::Reinsert`] without an insert function.
+    pub fn register_ref_component<T: Bundle>(&mut self, registration: RefCompRegistration<T>) {
+        assert_reinsertable::<T>(
+            registration.removal_policy,
+            registration.insert_fn.is_some(),
+        );
+        self.register_spawner::<T>();
+        let spawner = self
+            .comp_spawner
+            .get_mut(&RefCompTypeId::of::<T>())
+            .expect("the spawner was just registered");
+        spawner.release_policy = registration.release_policy;
+        spawner.removal_policy = registration.removal_policy;
+        spawner.despawn_policy = registration.despawn_policy;
+        spawner.default_insert = registration.insert_fn;
+        if let Some(name) = registration.name {
+            RefCompTypeId::of::<T>().set_name(name);
+        }
+    }
+
+    /// Makes `RefCompHandle<T>` components that were saved as Strong and loaded through
+    /// reflection, for example by spawning a scene, Strong again in the next release pass.
+    ///
+    /// Only handles stored directly as components are restored, not handles nested inside other
+    /// components.
+    pub fn register_ref_handle<T: Bundle>(&mut self) {
+        self.handle_reattachers
+            .insert(RefCompTypeId::of::<T>(), reattach_handles::<T>());
+    }
+}
+
+/// A component that declares how the [`RefCompServer`] inserts and releases it, so call sites
+/// don't have to repeat it. Usually implemented with `#[derive(RefComponent)]`.
+pub trait RefComponent: Component + Sized {
+    /// Creates the component when the target entity does not have one yet.
+    fn insert_ref(world: &mut World, entity: Entity) -> Self;
+
+    /// Edits the component when the target entity already has one.
+    fn edit_fn() -> Option<fn(&mut World, Entity, &mut Self)> {
+        None
+    }
+
+    /// The policies the server manages this type with.
+    /// See [`RefCompAppExt::init_ref_component`].
+    fn ref_registration() -> RefCompRegistration<Self> {
+        RefCompRegistration::new().with_insert_fn(Self::insert_ref)
+    }
+
+    /// A builder that inserts this component on `entity` with the type's insert and edit
+    /// functions.
+    fn ref_builder(entity: Entity) -> RefCompBuilder<Self> {
+        let builder = RefCompBuilder::new(entity, Self::insert_ref);
+        match Self::edit_fn() {
+            Some(edit_fn) => builder.with_edit_fn(edit_fn),
+            None => builder,
+        }
+    }
+}
+
+/// Registers reference-counted types on an [`App`].
+pub trait RefCompAppExt {
+    /// Registers `T` with the [`RefCompServer`] using the default policies.
+    fn register_ref_component<T: Bundle>(&mut self) -> &mut Self;
+    /// Registers `T` with the [`RefCompServer`].
+    fn register_ref_component_with<T: Bundle>(
+        &mut self,
+        registration: RefCompRegistration<T>,
+    ) -> &mut Self;
+    /// Registers `T` with the [`RefCompServer`] using the policies it declares.
+    fn init_ref_component<T: RefComponent>(&mut self) -> &mut Self;
+    /// Registers `RefCompHandle<T>` for reflection, so that it can be saved in scenes, and
+    /// restores loaded Strong handles. See [`RefCompServer::register_ref_handle`].
+    fn register_ref_handle<T: Bundle>(&mut self) -> &mut Self;
+}
+
+impl RefCompAppExt for App {
+    fn register_ref_component<T: Bundle>(&mut self) -> &mut Self {
+        self.register_ref_component_with::<T>(RefCompRegistration::default())
+    }
+
+    fn register_ref_component_with<T: Bundle>(
+        &mut self,
+        registration: RefCompRegistration<T>,
+    ) -> &mut Self {
+        self.world
+            .get_resource_or_insert_with(RefCompServer::default)
+            .register_ref_component(registration);
+        self
+    }
+
+    fn init_ref_component<T: RefComponent>(&mut self) -> &mut Self {
+        self.register_ref_component_with(T::ref_registration())
+    }
+
+    fn register_ref_handle<T: Bundle>(&mut self) -> &mut Self {
+        self.register_type::<RefCompHandle<T>>();
+        self.world
+            .get_resource_or_insert_with(RefCompServer::default)
+            .register_ref_handle::<T>();
+        self
+    }
+}
@@ -0,0 +1,158 @@
+use bevy::{
+    prelude::{Entity, World},
+    utils::HashSet,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{handle_serde, RefCompHandleId, RefCompServer, RefCompTypeId};
+
+/// The components a [`RefCompServer`] manages and how many strong handles point at each, for
+/// saving alongside a save game. See [`RefCompServer::snapshot`].
+///
+/// Component types are stored by their stable name, see [`RefCompTypeId::name`], so that a
+/// snapshot still loads when one of its types no longer exists.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RefCompSnapshot {
+    /// The names of the component types registered with the server.
+    pub types: Vec<String>,
+    /// Every managed component. Components waiting on their release policy have a count of 0.
+    pub entries: Vec<RefCompSnapshotEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RefCompSnapshotEntry {
+    #[serde(with = "handle_serde::entity_bits")]
+    pub entity: Entity,
+    /// The stable name of the component type.
+    pub component: String,
+    pub count: usize,
+}
+
+/// What [`RefCompServer::restore`] found that doesn't match the snapshot.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RefCompRestoreReport {
+    /// Names of types in the snapshot that are not registered with the server, or that don't
+    /// belong to any type in this build.
+    pub unregistered_types: Vec<String>,
+    /// Entries whose entity or component does not exist in the world.
+    pub missing_components: Vec<RefCompHandleId>,
+    /// Entries whose count differs from the number of strong handles that exist.
+    pub count_mismatches: Vec<RefCountMismatch>,
+    /// Ids strong handles point at that are not in the snapshot.
+    pub unexpected_handles: Vec<RefCompHandleId>,
+}
+
+impl RefCompRestoreReport {
+    /// Returns true if the world and its handles match the snapshot.
+    pub fn is_empty(&self) -> bool {
+        self.unregistered_types.is_empty()
+            && self.missing_components.is_empty()
+            && self.count_mismatches.is_empty()
+            && self.unexpected_handles.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RefCountMismatch {
+    pub id: RefCompHandleId,
+    /// The count in the snapshot.
+    pub expected: usize,
+    /// The number of strong handles that exist.
+    pub found: usize,
+}
+
+impl RefCompServer {
+    /// Records which components the server manages and how many strong handles point at each.
+    pub fn snapshot(&self) -> RefCompSnapshot {
+        let mut types: Vec<String> = self
+            .comp_spawner
+            .keys()
+            .map(|type_id| type_id.saved_name().to_owned())
+            .collect();
+        types.sort();
+
+        let mut entries: Vec<RefCompSnapshotEntry> = self
+            .iter_ref_counts()
+            .filter(|(id, _)| !self.is_invalidated(id))
+            .chain(
+                self.pending_releases
+                    .keys()
+                    .filter(|id| self.ref_count(id) == 0)
+                    .map(|&id| (id, 0)),
+            )
+            .map(|(id, count)| RefCompSnapshotEntry {
+                entity: id.entity,
+                component: id.type_id.saved_name().to_owned(),
+                count,
+            })
+            .collect();
+        entries.sort_by(|a, b| (&a.component, a.entity).cmp(&(&b.component, b.entity)));
+
+        RefCompSnapshot { types, entries }
+    }
+
+    /// Re-establishes which components of `world` the server manages after loading a save.
+    ///
+    /// Counts are not taken from the snapshot, since every strong handle counts itself once it
+    /// is loaded, for example through [`RefCompServer::handle_seed`]. Restore after loading the
+    /// handles, so that their counts can be compared with the snapshot. Entries no handle points
+    /// at are released according to their [`ReleasePolicy`](crate::ReleasePolicy). Entries of
+    /// types that are not registered are skipped and reported.
+    pub fn restore(&mut self, world: &World, snapshot: &RefCompSnapshot) -> RefCompRestoreReport {
+        let mut report = RefCompRestoreReport::default();
+        let registered = |name: &str| {
+            RefCompTypeId::from_name(name).filter(|type_id| self.comp_spawner.contains_key(type_id))
+        };
+        for name in &snapshot.types {
+            if registered(name).is_none() {
+                report.unregistered_types.push(name.clone());
+            }
+        }
+        let mut restored = HashSet::new();
+        let mut entries = Vec::new();
+        for entry in &snapshot.entries {
+            let Some(type_id) = registered(&entry.component) else {
+                if !report.unregistered_types.contains(&entry.component) {
+                    report.unregistered_types.push(entry.component.clone());
+                }
+                continue;
+            };
+            let id = RefCompHandleId {
+                entity: entry.entity,
+                type_id,
+            };
+            restored.insert(id);
+            entries.push((id, entry.count));
+        }
+
+        let now = self.now(world);
+        for (id, count) in entries {
+            let spawner = &self.comp_spawner[&id.type_id];
+            if world.get_entity(id.entity).is_none() || !(spawner.contains)(world, id.entity) {
+                report.missing_components.push(id);
+                continue;
+            }
+            self.record_despawn_policy(id);
+
+            let found = self.ref_count(&id);
+            if found != count {
+                report.count_mismatches.push(RefCountMismatch {
+                    id,
+                    expected: count,
+                    found,
+                });
+            }
+            if found == 0 {
+                self.pending_releases.entry(id).or_insert(now);
+            }
+        }
+
+        report.unexpected_handles = self
+            .iter_ref_counts()
+            .map(|(id, _)| id)
+            .filter(|id| !restored.contains(id))
+            .collect();
+        report.unexpected_handles.sort();
+        report
+    }
+}
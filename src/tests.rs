@@ -1,6 +1,32 @@
-use bevy::prelude::*;
+use std::{any::type_name, time::Duration};
 
-use crate::{RefCompBuilder, RefCompExt, RefCompHandle, RefCompPlugin, RefCompServer};
+use bevy::{
+    core::FrameCountPlugin,
+    ecs::{
+        entity::{EntityMap, MapEntities},
+        reflect::ReflectMapEntities,
+        schedule::ScheduleLabel,
+        system::SystemState,
+    },
+    prelude::*,
+    reflect::serde::UntypedReflectDeserializer,
+};
+use serde::{
+    de::{
+        value::{self, MapDeserializer, SeqDeserializer},
+        DeserializeSeed, IntoDeserializer, Visitor,
+    },
+    forward_to_deserialize_any, Deserialize, Deserializer,
+};
+
+use crate::{
+    DespawnPolicy, RefChange, RefCompAppExt, RefCompBuilder, RefCompEdited,
+    RefCompEntityCommandsExt, RefCompEntityMutExt, RefCompError, RefCompExt, RefCompHandle,
+    RefCompHandleId, RefCompHandleUntyped, RefCompInserted, RefCompInvalidated, RefCompPlugin,
+    RefCompReflectError, RefCompRegistration, RefCompReleased, RefCompServer, RefCompSet,
+    RefCompSnapshot, RefCompSnapshotEntry, RefCompTypeId, RefComponent, RefComps, RefCompsMut,
+    RefCountError, RefCountMismatch, RefEntity, ReleasePolicy, RemovalPolicy,
+};
 
 /// Tests if the RefCompServer will insert components that do not currently exist,
 /// and if it will remove components that are no longer referenced
@@ -8,8 +34,8 @@ use crate::{RefCompBuilder, RefCompExt, RefCompHandle, RefCompPlugin, RefCompSer
 fn test_insert() {
     let mut app = App::new();
 
-    app.add_plugin(RefCompPlugin).add_startup_system(
-        |mut commands: Commands, mut ref_comp_server: ResMut<RefCompServer>| {
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, ref_comp_server: ResMut<RefCompServer>| {
             let foo_ent = commands.spawn_empty().id();
             commands.insert_resource(EntityRef(foo_ent));
 
@@ -37,8 +63,8 @@ fn test_insert() {
 fn test_multi_remove() {
     let mut app = App::new();
 
-    app.add_plugin(RefCompPlugin).add_startup_system(
-        |mut commands: Commands, mut ref_comp_server: ResMut<RefCompServer>| {
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, ref_comp_server: ResMut<RefCompServer>| {
             let foo_ent = commands.spawn_empty().id();
             commands.insert_resource(EntityRef(foo_ent));
 
@@ -62,14 +88,82 @@ fn test_multi_remove() {
     assert!(world.entity(foo_ent.0).contains::<Foo>())
 }
 
+/// Tests that dropping a handle and getting a new one to the same component in the same
+/// frame does not remove the component.
+#[test]
+fn test_drop_and_reacquire_same_frame() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, ref_comp_server: Res<RefCompServer>| {
+            let foo_ent = commands.spawn_empty().id();
+            commands.insert_resource(EntityRef(foo_ent));
+
+            let handle = ref_comp_server.insert_ref_comp_fw::<Foo>(&mut commands, foo_ent, None);
+            commands.insert_resource(FooHandleRes1(handle));
+        },
+    );
+
+    app.update();
+
+    let world = &mut app.world;
+    let foo_ent = world.resource::<EntityRef>().0;
+    world.remove_resource::<FooHandleRes1>();
+    let handle = world.insert_ref_comp_from_world::<Foo>(foo_ent, None);
+    world.insert_resource(FooHandleRes2(handle));
+    app.update();
+
+    let world = &mut app.world;
+    assert!(world.entity(foo_ent).contains::<Foo>());
+    assert_eq!(world.resource::<RefCompServer>().ref_counts.len(), 1);
+
+    world.remove_resource::<FooHandleRes2>();
+    app.update();
+
+    let world = &mut app.world;
+    assert!(!world.entity(foo_ent).contains::<Foo>());
+}
+
+/// Tests that several drops and new handles in one frame are decided from the final count.
+#[test]
+fn test_multi_drop_and_reacquire_same_frame() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, ref_comp_server: Res<RefCompServer>| {
+            let foo_ent = commands.spawn_empty().id();
+            commands.insert_resource(EntityRef(foo_ent));
+
+            let handle = ref_comp_server.insert_ref_comp_fw::<Foo>(&mut commands, foo_ent, None);
+            commands.insert_resource(FooHandleRes1(handle));
+        },
+    );
+
+    app.update();
+
+    let world = &mut app.world;
+    let foo_ent = world.resource::<EntityRef>().0;
+    let id = world.resource::<FooHandleRes1>().0.id;
+    world.remove_resource::<FooHandleRes1>();
+    let handle = world.resource::<RefCompServer>().get_handle::<Foo, _>(id);
+    drop(handle);
+    let handle = world.resource::<RefCompServer>().get_handle::<Foo, _>(id);
+    world.insert_resource(FooHandleRes1(handle));
+    app.update();
+
+    let world = &mut app.world;
+    assert!(world.entity(foo_ent).contains::<Foo>());
+    assert_eq!(world.resource::<RefCompServer>().ref_counts[&id], 1);
+}
+
 /// Tests to see if we can add a function to return a new copy of the component
 /// to insert for the RefCompServer.
 #[test]
 fn test_insert_function() {
     let mut app = App::new();
 
-    app.add_plugin(RefCompPlugin).add_startup_system(
-        |mut commands: Commands, mut ref_comp_server: ResMut<RefCompServer>| {
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, ref_comp_server: ResMut<RefCompServer>| {
             let entity = commands.spawn_empty().id();
             commands.insert_resource(EntityRef(entity));
 
@@ -108,8 +202,8 @@ fn test_insert_function() {
 fn test_insert_function_overwrite() {
     let mut app = App::new();
 
-    app.add_plugin(RefCompPlugin).add_startup_system(
-        |mut commands: Commands, mut ref_comp_server: ResMut<RefCompServer>| {
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, ref_comp_server: ResMut<RefCompServer>| {
             let entity = commands.spawn_empty().id();
             commands.insert_resource(EntityRef(entity));
 
@@ -164,8 +258,8 @@ fn test_insert_function_overwrite() {
 fn test_insert_edit_function() {
     let mut app = App::new();
 
-    app.add_plugin(RefCompPlugin).add_startup_system(
-        |mut commands: Commands, mut ref_comp_server: ResMut<RefCompServer>| {
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, ref_comp_server: ResMut<RefCompServer>| {
             let entity = commands.spawn_empty().id();
             commands.insert_resource(EntityRef(entity));
 
@@ -213,8 +307,8 @@ fn test_insert_edit_function() {
 fn test_builder() {
     let mut app = App::new();
 
-    app.add_plugin(RefCompPlugin).add_startup_system(
-        |mut commands: Commands, mut ref_comp_server: ResMut<RefCompServer>| {
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, ref_comp_server: ResMut<RefCompServer>| {
             let entity = commands.spawn_empty().id();
             commands.insert_resource(EntityRef(entity));
 
@@ -222,7 +316,7 @@ fn test_builder() {
                 string: "I am a test string!".to_string(),
                 integer: 42,
             })
-            .build(&mut commands, &mut ref_comp_server);
+            .build(&mut commands, &ref_comp_server);
             /*             let handle = ref_comp_server.add_ref_comp::<Bar>(
                 &mut commands,
                 entity,
@@ -252,26 +346,1418 @@ fn test_builder() {
     assert!(!world.entity(bar_ent.0).contains::<Bar>())
 }
 
-#[derive(Component, Default)]
-struct Foo;
+/// Tests to see if the insert and edit functions can capture local data.
+#[test]
+fn test_capturing_closures() {
+    let mut app = App::new();
 
-#[derive(Resource)]
-struct EntityRef(Entity);
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, ref_comp_server: Res<RefCompServer>| {
+            let entity = commands.spawn_empty().id();
+            commands.insert_resource(EntityRef(entity));
 
-#[derive(Resource)]
-struct FooHandleRes1(RefCompHandle<Foo>);
+            let string = "I was captured!".to_string();
+            let integer = 7;
+            let handle = ref_comp_server.insert_ref_comp::<Bar>(
+                &mut commands,
+                entity,
+                move |_world, _entity| Bar { string, integer },
+                None,
+            );
+            commands.insert_resource(BarHandleRes1(handle));
+        },
+    );
 
-#[derive(Resource)]
-struct FooHandleRes2(RefCompHandle<Foo>);
+    app.update();
 
-#[derive(Component, Default)]
-struct Bar {
-    string: String,
-    integer: u32,
+    let world = &mut app.world;
+    let bar_ent = world.resource::<EntityRef>().0;
+    let bar = world.entity(bar_ent).get::<Bar>().unwrap();
+    assert!(bar.integer == 7);
+    assert!(bar.string == "I was captured!");
+
+    let offset = 5;
+    let handle = RefCompBuilder::new(bar_ent, |_world, _entity| Bar::default())
+        .with_edit_fn(move |_world, _entity, bar| bar.integer += offset)
+        .build_world(world);
+    world.insert_resource(BarHandleRes2(handle));
+
+    let bar = world.entity(bar_ent).get::<Bar>().unwrap();
+    assert!(bar.integer == 12);
+
+    let suffix = " And edited!".to_string();
+    let _handle = world.insert_ref_comp_with_edit(
+        bar_ent,
+        |_world, _entity| Bar::default(),
+        move |_world, _entity, bar: &mut Bar| bar.string.push_str(&suffix),
+    );
+
+    let bar = world.entity(bar_ent).get::<Bar>().unwrap();
+    assert!(bar.string == "I was captured! And edited!");
 }
 
-#[derive(Resource)]
-struct BarHandleRes1(RefCompHandle<Bar>);
+/// Tests that the server drops its bookkeeping for despawned entities and that handles
+/// report the despawn, even after the entity index is reused.
+#[test]
+fn test_despawned_entity() {
+    let mut app = App::new();
 
-#[derive(Resource)]
-struct BarHandleRes2(RefCompHandle<Bar>);
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, ref_comp_server: Res<RefCompServer>| {
+            let foo_ent = commands.spawn_empty().id();
+            commands.insert_resource(EntityRef(foo_ent));
+
+            let handle = ref_comp_server.insert_ref_comp_fw::<Foo>(&mut commands, foo_ent, None);
+            commands.insert_resource(FooHandleRes1(handle));
+        },
+    );
+
+    app.update();
+
+    let world = &mut app.world;
+    let foo_ent = world.resource::<EntityRef>().0;
+    assert!(world.resource::<FooHandleRes1>().0.is_alive(world));
+    world.despawn(foo_ent);
+    // Handles report the despawn before the server has seen it.
+    let weak = world.resource::<FooHandleRes1>().0.clone_weak();
+    assert!(!weak.is_alive(world));
+    assert!(!world.resource::<FooHandleRes1>().0.is_alive(world));
+    app.update();
+
+    let world = &mut app.world;
+    assert!(!world.resource::<FooHandleRes1>().0.is_alive(world));
+    assert!(world.resource::<RefCompServer>().ref_counts.is_empty());
+
+    let recycled = world.spawn_empty().id();
+    assert_eq!(recycled.index(), foo_ent.index());
+    let handle = world.insert_ref_comp_from_world::<Foo>(recycled, None);
+    assert!(handle.is_alive(world));
+
+    world.remove_resource::<FooHandleRes1>();
+    app.update();
+
+    let world = &mut app.world;
+    assert!(world.resource::<RefCompServer>().orphaned.is_empty());
+    assert!(handle.is_alive(world));
+    assert!(!weak.is_alive(world));
+    assert!(world.entity(recycled).contains::<Foo>());
+}
+
+/// Tests that the server forgets pending releases and despawn policies of despawned entities,
+/// even ones that no strong handle points at anymore.
+#[test]
+fn test_despawned_pending_release() {
+    let mut world = World::new();
+    let mut server = RefCompServer::default();
+    server.set_default_release_policy(ReleasePolicy::Never);
+    world.insert_resource(server);
+
+    let foo_ent = world.spawn_empty().id();
+    let handle = world.insert_ref_comp_from_world::<Foo>(foo_ent, None);
+    let bare_ent = world.spawn_empty().id();
+    world
+        .resource_mut::<RefCompServer>()
+        .set_entity_despawn_policy(bare_ent, DespawnPolicy::Despawn);
+    drop(handle);
+    world.flush_ref_comps();
+    assert_eq!(
+        world.resource::<RefCompServer>().snapshot().entries.len(),
+        1
+    );
+
+    world.despawn(foo_ent);
+    world.despawn(bare_ent);
+    world.flush_ref_comps();
+
+    let server = world.resource::<RefCompServer>();
+    assert!(server.pending_releases.is_empty());
+    assert!(server.entity_despawn_policies.is_empty());
+    assert!(server.snapshot().entries.is_empty());
+}
+
+/// Tests that a component removed by something other than the server is inserted again
+/// through the insert function its type was registered with.
+#[test]
+fn test_external_removal_reinsert() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default())
+        .register_ref_component_with(
+            RefCompRegistration::<Bar>::new()
+                .with_removal_policy(RemovalPolicy::Reinsert)
+                .with_insert_fn(|_world, _entity| Bar {
+                    string: "I was reinserted!".to_string(),
+                    integer: 42,
+                }),
+        )
+        .add_startup_system(
+            |mut commands: Commands, ref_comp_server: Res<RefCompServer>| {
+                let entity = commands.spawn_empty().id();
+                commands.insert_resource(EntityRef(entity));
+
+                let handle = ref_comp_server.insert_ref_comp::<Bar>(
+                    &mut commands,
+                    entity,
+                    |_world, _entity| Bar {
+                        string: "I am a test string!".to_string(),
+                        integer: 42,
+                    },
+                    None,
+                );
+                commands.insert_resource(BarHandleRes1(handle));
+            },
+        );
+
+    app.update();
+
+    let world = &mut app.world;
+    let bar_ent = world.resource::<EntityRef>().0;
+    world.entity_mut(bar_ent).remove::<Bar>();
+    app.update();
+
+    let world = &mut app.world;
+    let bar = world.entity(bar_ent).get::<Bar>().unwrap();
+    assert!(bar.string == "I was reinserted!");
+    assert!(world.resource::<BarHandleRes1>().0.is_alive(world));
+}
+
+/// Tests that a type can't use the reinsert policy without an insert function to reinsert it
+/// with.
+#[test]
+#[should_panic(expected = "has no insert function")]
+fn test_reinsert_without_insert_fn() {
+    let mut server = RefCompServer::default();
+    server.set_removal_policy::<Bar>(RemovalPolicy::Reinsert);
+}
+
+/// Tests that a component removed by something other than the server invalidates its
+/// handles and sends an event.
+#[test]
+fn test_external_removal_invalidate() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, ref_comp_server: Res<RefCompServer>| {
+            let foo_ent = commands.spawn_empty().id();
+            commands.insert_resource(EntityRef(foo_ent));
+
+            let handle = ref_comp_server.insert_ref_comp_fw::<Foo>(&mut commands, foo_ent, None);
+            commands.insert_resource(FooHandleRes1(handle));
+        },
+    );
+
+    app.update();
+
+    let world = &mut app.world;
+    let foo_ent = world.resource::<EntityRef>().0;
+    world.entity_mut(foo_ent).remove::<Foo>();
+    app.update();
+
+    let world = &mut app.world;
+    let handle = &world.resource::<FooHandleRes1>().0;
+    assert!(!handle.is_alive(world));
+    let events = world.resource::<Events<RefCompInvalidated>>();
+    let ids: Vec<_> = events.get_reader().iter(events).map(|e| e.id).collect();
+    assert_eq!(ids, vec![handle.id]);
+
+    // Inserting the component again makes the old handles refer to it.
+    let handle = world.insert_ref_comp_from_world::<Foo>(foo_ent, None);
+    assert!(handle.is_alive(world));
+    assert!(world.resource::<FooHandleRes1>().0.is_alive(world));
+}
+
+/// Tests that a component whose insert command has not been applied yet when the release
+/// system runs is not taken for an externally removed one, also on an entity spawned by the
+/// same system.
+#[test]
+fn test_queued_insert_not_removed() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin {
+        release_schedule: Box::new(CoreSchedule::Main),
+        release_set: None,
+    })
+    .register_ref_component_with(
+        RefCompRegistration::<Bar>::new().with_removal_policy(RemovalPolicy::Panic),
+    )
+    .add_system(
+        (|mut commands: Commands,
+          ref_comp_server: Res<RefCompServer>,
+          entity: Res<EntityRef>,
+          handle: Option<Res<BarHandleRes1>>| {
+            if handle.is_none() {
+                let handle =
+                    ref_comp_server.insert_ref_comp_fw::<Bar>(&mut commands, entity.0, None);
+                commands.insert_resource(BarHandleRes1(handle));
+                let spawned = commands.spawn_empty().id();
+                let handle =
+                    ref_comp_server.insert_ref_comp_fw::<Bar>(&mut commands, spawned, None);
+                commands.insert_resource(BarHandleRes2(handle));
+            }
+        })
+        .before(RefCompSet::Release),
+    );
+    let entity = app.world.spawn_empty().id();
+    app.world.insert_resource(EntityRef(entity));
+
+    app.update();
+    app.update();
+
+    let world = &app.world;
+    for handle in [
+        &world.resource::<BarHandleRes1>().0,
+        &world.resource::<BarHandleRes2>().0,
+    ] {
+        assert!(world.entity(handle.id.entity).contains::<Bar>());
+        assert!(handle.is_alive(world));
+    }
+    assert!(world.resource::<Events<RefCompInvalidated>>().is_empty());
+}
+
+/// Tests resolving handles to their components through the system params.
+#[test]
+fn test_ref_comps_param() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, ref_comp_server: Res<RefCompServer>| {
+            let entity = commands.spawn_empty().id();
+            commands.insert_resource(EntityRef(entity));
+
+            let handle = ref_comp_server.insert_ref_comp_fw::<Bar>(&mut commands, entity, None);
+            commands.insert_resource(BarHandleRes1(handle));
+        },
+    );
+
+    app.update();
+
+    app.add_system(|handle: Res<BarHandleRes1>, mut bars: RefCompsMut<Bar>| {
+        bars.get_mut(&handle.0).unwrap().integer = 42;
+    });
+    app.update();
+
+    let world = &mut app.world;
+    let bar_ent = world.resource::<EntityRef>().0;
+    let id = world.resource::<BarHandleRes1>().0.id;
+
+    let mut state: SystemState<RefComps<Bar>> = SystemState::new(world);
+    let bars = state.get(world);
+    assert_eq!(bars.get(&RefCompHandle::weak(id)).unwrap().integer, 42);
+    let handles: Vec<_> = bars.iter_handles().map(|(handle, _)| handle.id).collect();
+    assert_eq!(handles, vec![id]);
+    assert_eq!(
+        bars.get(&RefCompHandle::weak(RefCompHandleId::new::<Foo>(bar_ent)))
+            .err(),
+        Some(RefCompError::WrongType(RefCompHandleId::new::<Foo>(
+            bar_ent
+        )))
+    );
+
+    world.entity_mut(bar_ent).remove::<Bar>();
+    let bars = state.get(world);
+    assert_eq!(
+        bars.get(&RefCompHandle::weak(id)).err(),
+        Some(RefCompError::MissingComponent(id))
+    );
+
+    world.despawn(bar_ent);
+    let bars = state.get(world);
+    assert_eq!(
+        bars.get(&RefCompHandle::weak(id)).err(),
+        Some(RefCompError::NoSuchEntity(id))
+    );
+}
+
+/// Tests that the server sends events when it inserts, edits and releases components.
+#[test]
+fn test_lifecycle_events() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, ref_comp_server: Res<RefCompServer>| {
+            let entity = commands.spawn_empty().id();
+            commands.insert_resource(EntityRef(entity));
+
+            let handle = ref_comp_server.insert_ref_comp_fw::<Bar>(&mut commands, entity, None);
+            commands.insert_resource(BarHandleRes1(handle));
+        },
+    );
+
+    app.update();
+
+    let world = &mut app.world;
+    let id = world.resource::<BarHandleRes1>().0.id;
+    let inserted = world.resource::<Events<RefCompInserted>>();
+    let ids: Vec<_> = inserted.get_reader().iter(inserted).map(|e| e.id).collect();
+    assert_eq!(ids, vec![id]);
+
+    let handle = world
+        .insert_ref_comp_from_world::<Bar>(id.entity, Some(|_world, _entity, bar| bar.integer = 1));
+    world.insert_resource(BarHandleRes2(handle));
+    let edited = world.resource::<Events<RefCompEdited>>();
+    let ids: Vec<_> = edited.get_reader().iter(edited).map(|e| e.id).collect();
+    assert_eq!(ids, vec![id]);
+
+    world.remove_resource::<BarHandleRes1>();
+    world.remove_resource::<BarHandleRes2>();
+    app.update();
+
+    let world = &mut app.world;
+    let released = world.resource::<Events<RefCompReleased>>();
+    let ids: Vec<_> = released.get_reader().iter(released).map(|e| e.id).collect();
+    assert_eq!(ids, vec![id]);
+}
+
+/// Tests that a delayed release waits the given number of frames, and that getting a new
+/// handle in the meantime cancels it.
+#[test]
+fn test_release_policy_frames() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, mut ref_comp_server: ResMut<RefCompServer>| {
+            let foo_ent = commands.spawn_empty().id();
+            commands.insert_resource(EntityRef(foo_ent));
+
+            ref_comp_server.set_release_policy::<Foo>(ReleasePolicy::Frames(1));
+            let handle = ref_comp_server.insert_ref_comp_fw::<Foo>(&mut commands, foo_ent, None);
+            commands.insert_resource(FooHandleRes1(handle));
+        },
+    );
+
+    app.update();
+
+    let world = &mut app.world;
+    let foo_ent = world.resource::<EntityRef>().0;
+    world.remove_resource::<FooHandleRes1>();
+    app.update();
+
+    let world = &mut app.world;
+    assert!(world.entity(foo_ent).contains::<Foo>());
+    let handle = world.insert_ref_comp_from_world::<Foo>(foo_ent, None);
+    world.insert_resource(FooHandleRes1(handle));
+    app.update();
+    app.update();
+
+    let world = &mut app.world;
+    assert!(world.entity(foo_ent).contains::<Foo>());
+    world.remove_resource::<FooHandleRes1>();
+    app.update();
+
+    let world = &mut app.world;
+    assert!(world.entity(foo_ent).contains::<Foo>());
+    app.update();
+
+    let world = &mut app.world;
+    assert!(!world.entity(foo_ent).contains::<Foo>());
+}
+
+/// Tests that release delays count frames rather than release passes when the world has a
+/// `FrameCount`.
+#[test]
+fn test_release_policy_frame_count() {
+    let mut app = App::new();
+    app.add_plugin(FrameCountPlugin)
+        .add_plugin(RefCompPlugin::default());
+    app.world
+        .resource_mut::<RefCompServer>()
+        .set_release_policy::<Foo>(ReleasePolicy::Frames(1));
+    let foo_ent = app.world.spawn_empty().id();
+    let handle = app.world.insert_ref_comp_from_world::<Foo>(foo_ent, None);
+    app.update();
+
+    drop(handle);
+    app.world.flush_ref_comps();
+    app.world.flush_ref_comps();
+    assert!(app.world.entity(foo_ent).contains::<Foo>());
+    app.update();
+    assert!(app.world.entity(foo_ent).contains::<Foo>());
+    app.update();
+    assert!(!app.world.entity(foo_ent).contains::<Foo>());
+}
+
+/// Tests that a release waits on [`Time`], and on real time when the world has no [`Time`].
+#[test]
+fn test_release_policy_duration() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default())
+        .init_resource::<Time>()
+        .add_startup_system(
+            |mut commands: Commands, mut ref_comp_server: ResMut<RefCompServer>| {
+                let foo_ent = commands.spawn_empty().id();
+                commands.insert_resource(EntityRef(foo_ent));
+
+                ref_comp_server
+                    .set_release_policy::<Foo>(ReleasePolicy::Duration(Duration::from_secs(1)));
+                let handle =
+                    ref_comp_server.insert_ref_comp_fw::<Foo>(&mut commands, foo_ent, None);
+                commands.insert_resource(FooHandleRes1(handle));
+            },
+        );
+
+    app.update();
+
+    let world = &mut app.world;
+    let foo_ent = world.resource::<EntityRef>().0;
+    let startup = world.resource::<Time>().startup();
+    world.remove_resource::<FooHandleRes1>();
+    app.update();
+    app.update();
+
+    let world = &mut app.world;
+    assert!(world.entity(foo_ent).contains::<Foo>());
+    world
+        .resource_mut::<Time>()
+        .update_with_instant(startup + Duration::from_secs(2));
+    app.update();
+
+    let world = &mut app.world;
+    assert!(!world.entity(foo_ent).contains::<Foo>());
+
+    let mut world = World::new();
+    world.init_resource::<RefCompServer>();
+    world
+        .resource_mut::<RefCompServer>()
+        .set_release_policy::<Foo>(ReleasePolicy::Duration(Duration::from_millis(10)));
+    let foo_ent = world.spawn_empty().id();
+    drop(world.insert_ref_comp_from_world::<Foo>(foo_ent, None));
+    world.flush_ref_comps();
+    assert!(world.entity(foo_ent).contains::<Foo>());
+
+    std::thread::sleep(Duration::from_millis(20));
+    let report = world.flush_ref_comps();
+    assert_eq!(report.released, vec![RefCompHandleId::new::<Foo>(foo_ent)]);
+}
+
+/// Tests that components are kept until a manual sweep when releasing is disabled.
+#[test]
+fn test_release_policy_never() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, mut ref_comp_server: ResMut<RefCompServer>| {
+            let foo_ent = commands.spawn_empty().id();
+            commands.insert_resource(EntityRef(foo_ent));
+
+            ref_comp_server.set_default_release_policy(ReleasePolicy::Never);
+            let handle = ref_comp_server.insert_ref_comp_fw::<Foo>(&mut commands, foo_ent, None);
+            commands.insert_resource(FooHandleRes1(handle));
+        },
+    );
+
+    app.update();
+
+    let world = &mut app.world;
+    let foo_ent = world.resource::<EntityRef>().0;
+    world.remove_resource::<FooHandleRes1>();
+    app.update();
+    app.update();
+
+    let world = &mut app.world;
+    assert!(world.entity(foo_ent).contains::<Foo>());
+    world.resource_scope(|world, mut server: Mut<RefCompServer>| server.sweep(world));
+    assert!(!world.entity(foo_ent).contains::<Foo>());
+}
+
+/// Tests that an entity is only despawned once every component the server manages on it
+/// has been released.
+#[test]
+fn test_despawn_policy() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, ref_comp_server: Res<RefCompServer>| {
+            let entity = commands.spawn_empty().id();
+            commands.insert_resource(EntityRef(entity));
+
+            let handle = RefCompBuilder::<Foo>::new_fw(entity)
+                .with_despawn_policy(DespawnPolicy::Despawn)
+                .build(&mut commands, &ref_comp_server);
+            commands.insert_resource(FooHandleRes1(handle));
+            let handle = ref_comp_server.insert_ref_comp_fw::<Bar>(&mut commands, entity, None);
+            commands.insert_resource(BarHandleRes1(handle));
+        },
+    );
+
+    app.update();
+
+    let world = &mut app.world;
+    let entity = world.resource::<EntityRef>().0;
+    world.remove_resource::<FooHandleRes1>();
+    app.update();
+
+    let world = &mut app.world;
+    assert!(!world.entity(entity).contains::<Foo>());
+    world.remove_resource::<BarHandleRes1>();
+    app.update();
+
+    let world = &mut app.world;
+    assert!(world.get_entity(entity).is_none());
+}
+
+/// Tests that an entity spawned through the server lives as long as handles to it do.
+#[test]
+fn test_spawn_ref() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, ref_comp_server: Res<RefCompServer>| {
+            let handle = ref_comp_server.spawn_ref(&mut commands, Bar::default());
+            commands.insert_resource(EntityRef(handle.id.entity));
+            commands.insert_resource(RefEntityRes(handle));
+        },
+    );
+
+    app.update();
+
+    let world = &mut app.world;
+    let entity = world.resource::<EntityRef>().0;
+    assert!(world.entity(entity).contains::<Bar>());
+    let handle = world.spawn_ref(Foo);
+    let foo_ent = handle.id.entity;
+    world.remove_resource::<RefEntityRes>();
+    app.update();
+
+    let world = &mut app.world;
+    assert!(world.get_entity(entity).is_none());
+    assert!(world.entity(foo_ent).contains::<Foo>());
+    drop(handle);
+    app.update();
+
+    let world = &mut app.world;
+    assert!(world.get_entity(foo_ent).is_none());
+}
+
+/// Tests that a reference-counted bundle is inserted and removed as a whole.
+#[test]
+fn test_ref_bundle() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, ref_comp_server: Res<RefCompServer>| {
+            let entity = commands.spawn_empty().id();
+            commands.insert_resource(EntityRef(entity));
+
+            let handle = ref_comp_server.insert_ref_bundle(
+                &mut commands,
+                entity,
+                |_world, _entity| (Foo, Bar::default()),
+                None,
+            );
+            commands.insert_resource(BundleHandleRes(handle));
+        },
+    );
+
+    app.update();
+
+    let world = &mut app.world;
+    let entity = world.resource::<EntityRef>().0;
+    assert!(world.entity(entity).contains::<Foo>());
+    assert!(world.entity(entity).contains::<Bar>());
+
+    world.remove_resource::<BundleHandleRes>();
+    app.update();
+
+    let world = &mut app.world;
+    assert!(!world.entity(entity).contains::<Foo>());
+    assert!(!world.entity(entity).contains::<Bar>());
+}
+
+/// Tests inserting through the entity command and entity world extensions.
+#[test]
+fn test_entity_ext() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default()).add_startup_system(
+        |mut commands: Commands, ref_comp_server: Res<RefCompServer>| {
+            let mut entity_commands = commands.spawn_empty();
+            let handle = entity_commands.insert_ref_fw::<Foo>(&ref_comp_server, None);
+            commands.insert_resource(EntityRef(handle.id.entity));
+            commands.insert_resource(FooHandleRes1(handle));
+        },
+    );
+
+    app.update();
+
+    let world = &mut app.world;
+    let entity = world.resource::<EntityRef>().0;
+    assert!(world.entity(entity).contains::<Foo>());
+    let handle = world.entity_mut(entity).insert_ref(
+        |_world, _entity| Bar {
+            string: "I am a test string!".to_string(),
+            integer: 42,
+        },
+        None,
+    );
+    assert_eq!(world.entity(entity).get::<Bar>().unwrap().integer, 42);
+
+    world.remove_resource::<FooHandleRes1>();
+    drop(handle);
+    app.update();
+
+    let world = &mut app.world;
+    assert!(!world.entity(entity).contains::<Foo>());
+    assert!(!world.entity(entity).contains::<Bar>());
+}
+
+/// Tests that systems only need shared access to the server to hand out references.
+#[test]
+fn test_shared_server_access() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default())
+        .add_startup_system(|mut commands: Commands| {
+            let entity = commands.spawn_empty().id();
+            commands.insert_resource(EntityRef(entity));
+        })
+        .add_system(
+            |mut commands: Commands, entity: Res<EntityRef>, server: Res<RefCompServer>| {
+                let handle = server.insert_ref_comp_fw::<Foo>(&mut commands, entity.0, None);
+                commands.insert_resource(FooHandleRes1(handle));
+            },
+        )
+        .add_system(
+            |mut commands: Commands, entity: Res<EntityRef>, server: Res<RefCompServer>| {
+                let handle = RefCompBuilder::<Bar>::new_fw(entity.0).build(&mut commands, &server);
+                commands.insert_resource(BarHandleRes1(handle));
+            },
+        );
+
+    app.update();
+
+    let world = &mut app.world;
+    let entity = world.resource::<EntityRef>().0;
+    assert!(world.entity(entity).contains::<Foo>());
+    assert!(world.entity(entity).contains::<Bar>());
+}
+
+/// Tests that handles to a registered type are released even if the server never inserted
+/// the component, and that the registered insert function is used.
+#[test]
+fn test_register_ref_component() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default())
+        .register_ref_component::<Foo>()
+        .register_ref_component_with(RefCompRegistration::new().with_insert_fn(
+            |_world: &mut World, _entity| Bar {
+                string: "I am a test string!".to_string(),
+                integer: 42,
+            },
+        ));
+
+    let world = &mut app.world;
+    let entity = world.spawn(Foo).id();
+    let mut handle = RefCompHandleUntyped::weak_from_entity::<Foo>(entity).typed::<Foo>();
+    handle.make_strong(world.resource());
+    world.insert_resource(FooHandleRes1(handle));
+
+    let mut commands_state: SystemState<(Commands, Res<RefCompServer>)> = SystemState::new(world);
+    let (mut commands, server) = commands_state.get_mut(world);
+    let handle = server.insert_ref_comp_default::<Bar>(&mut commands, entity);
+    commands_state.apply(world);
+    world.insert_resource(BarHandleRes1(handle));
+    assert_eq!(world.entity(entity).get::<Bar>().unwrap().integer, 42);
+
+    app.update();
+
+    let world = &mut app.world;
+    world.remove_resource::<FooHandleRes1>();
+    world.remove_resource::<BarHandleRes1>();
+    app.update();
+
+    let world = &mut app.world;
+    assert!(!world.entity(entity).contains::<Foo>());
+    assert!(!world.entity(entity).contains::<Bar>());
+}
+
+/// Tests that dropping a strong handle the server never counted is reported instead of
+/// underflowing the count.
+#[test]
+fn test_unmatched_decrement() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default());
+
+    let world = &mut app.world;
+    let entity = world.spawn(Foo).id();
+    let id = RefCompHandleId::new::<Foo>(entity);
+    let server = world.resource::<RefCompServer>();
+    server
+        .channel
+        .sender
+        .send(RefChange::Decrement(id))
+        .unwrap();
+    app.update();
+
+    let world = &mut app.world;
+    assert!(world.resource::<RefCompServer>().ref_counts.is_empty());
+    let errors = world.resource::<Events<RefCountError>>();
+    let errors: Vec<_> = errors.get_reader().iter(errors).copied().collect();
+    assert_eq!(errors, vec![RefCountError::UnmatchedDecrement(id)]);
+}
+
+/// Tests that ref counts can be inspected, including changes the server has not applied yet.
+#[test]
+fn test_ref_count() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default());
+
+    let world = &mut app.world;
+    let entity = world.spawn_empty().id();
+    let handle = world.insert_ref_comp_from_world::<Foo>(entity, None);
+    let id = RefCompHandleId::new::<Foo>(entity);
+    app.update();
+
+    let world = &mut app.world;
+    let server = world.resource::<RefCompServer>();
+    assert_eq!(server.ref_count(&id), 1);
+
+    let clone = handle.clone();
+    assert_eq!(server.ref_count_of::<Foo>(entity), 2);
+    assert_eq!(server.iter_ref_counts().collect::<Vec<_>>(), vec![(id, 2)]);
+
+    drop(handle);
+    drop(clone);
+    assert_eq!(server.ref_count(&id), 0);
+    assert_eq!(server.iter_ref_counts().count(), 0);
+    app.update();
+
+    let world = &mut app.world;
+    assert!(!world.entity(entity).contains::<Foo>());
+    assert_eq!(world.resource::<RefCompServer>().ref_count(&id), 0);
+}
+
+/// Tests that weak handles only upgrade while their component is still tracked.
+#[test]
+fn test_try_upgrade() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default());
+
+    let world = &mut app.world;
+    let entity = world.spawn_empty().id();
+    let handle = world.insert_ref_comp_from_world::<Foo>(entity, None);
+    let weak = handle.clone_weak();
+    app.update();
+
+    let world = &mut app.world;
+    let upgraded = weak.try_upgrade(world.resource::<RefCompServer>()).unwrap();
+    assert!(upgraded.is_strong());
+    drop(handle);
+    app.update();
+
+    let world = &mut app.world;
+    assert!(world.entity(entity).contains::<Foo>());
+    drop(upgraded);
+    app.update();
+
+    let world = &mut app.world;
+    assert!(!world.entity(entity).contains::<Foo>());
+    let server = world.resource::<RefCompServer>();
+    assert!(weak.try_upgrade(server).is_none());
+    assert_eq!(server.ref_count(&weak.id), 0);
+}
+
+/// Tests that the release system can be moved into another schedule.
+#[test]
+fn test_release_schedule() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin {
+        release_schedule: Box::new(ReleaseSchedule),
+        release_set: None,
+    });
+
+    let world = &mut app.world;
+    let entity = world.spawn_empty().id();
+    let handle = world.insert_ref_comp_from_world::<Foo>(entity, None);
+    drop(handle);
+    app.update();
+
+    let world = &mut app.world;
+    assert!(world.entity(entity).contains::<Foo>());
+    world.run_schedule(ReleaseSchedule);
+    assert!(!world.entity(entity).contains::<Foo>());
+}
+
+/// Tests that the release system can be placed in a set that is not a base set, and ordered
+/// against through it.
+#[test]
+fn test_release_free_set() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin {
+        release_schedule: Box::new(CoreSchedule::Main),
+        release_set: Some(Box::new(ReleaseSet)),
+    })
+    .init_resource::<FooReleased>()
+    .add_system(
+        (|mut handle: ResMut<FooHandleOpt>| {
+            handle.0.take();
+        })
+        .before(ReleaseSet),
+    )
+    .add_system(
+        (|foos: Query<(), With<Foo>>, mut released: ResMut<FooReleased>| {
+            released.0 = foos.is_empty();
+        })
+        .after(ReleaseSet),
+    );
+
+    let world = &mut app.world;
+    let entity = world.spawn_empty().id();
+    let handle = world.insert_ref_comp_from_world::<Foo>(entity, None);
+    world.insert_resource(FooHandleOpt(Some(handle)));
+    app.update();
+
+    let world = &app.world;
+    assert!(world.resource::<FooReleased>().0);
+    assert!(!world.entity(entity).contains::<Foo>());
+}
+
+/// Tests that a bare world can release unreferenced components without an app schedule.
+#[test]
+fn test_flush_ref_comps() {
+    let mut world = World::new();
+    world.init_resource::<RefCompServer>();
+
+    let foo_ent = world.spawn_empty().id();
+    let foo_handle = world.insert_ref_comp_from_world::<Foo>(foo_ent, None);
+    let ref_handle = world.spawn_ref(Bar::default());
+    let ref_ent = ref_handle.id.entity;
+    assert!(world.flush_ref_comps().is_empty());
+
+    drop(foo_handle);
+    drop(ref_handle);
+    let report = world.flush_ref_comps();
+    assert!(!world.entity(foo_ent).contains::<Foo>());
+    assert!(world.get_entity(ref_ent).is_none());
+    assert_eq!(report.released.len(), 2);
+    assert_eq!(report.despawned, vec![ref_ent]);
+    assert!(report.invalidated.is_empty());
+}
+
+/// Tests that a derived ref component is inserted, edited and released as its attributes say.
+#[test]
+fn test_derive_ref_component() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default())
+        .init_ref_component::<Baz>();
+
+    let world = &mut app.world;
+    let entity = world.spawn_empty().id();
+    let handle1 = Baz::ref_builder(entity).build_world(world);
+    assert_eq!(world.get::<Baz>(entity).unwrap().0, 1);
+    let handle2 = Baz::ref_builder(entity).build_world(world);
+    assert_eq!(world.get::<Baz>(entity).unwrap().0, 2);
+    let foo_handle = Foo::ref_builder(entity).build_world(world);
+
+    drop(handle1);
+    drop(handle2);
+    drop(foo_handle);
+    app.update();
+
+    // Foo is released right away, Baz one frame later together with its entity.
+    let world = &mut app.world;
+    assert!(!world.entity(entity).contains::<Foo>());
+    assert!(world.entity(entity).contains::<Baz>());
+    app.update();
+
+    assert!(app.world.get_entity(entity).is_none());
+}
+
+/// Tests that an entity is despawned by the policy of a type that was released before the
+/// last component on it.
+#[test]
+fn test_despawn_policy_release_order() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default())
+        .init_ref_component::<Baz>();
+
+    let world = &mut app.world;
+    let entity = world.spawn_empty().id();
+    let baz_handle = Baz::ref_builder(entity).build_world(world);
+    let foo_handle = Foo::ref_builder(entity).build_world(world);
+
+    drop(baz_handle);
+    app.update();
+    app.update();
+
+    let world = &mut app.world;
+    assert!(!world.entity(entity).contains::<Baz>());
+    drop(foo_handle);
+    app.update();
+
+    assert!(app.world.get_entity(entity).is_none());
+}
+
+/// Tests that components only known through reflection are inserted and released.
+#[test]
+fn test_insert_ref_comp_reflect() {
+    let mut app = App::new();
+
+    app.add_plugin(RefCompPlugin::default())
+        .register_type::<Health>();
+
+    let world = &mut app.world;
+    let deserialize_health = |world: &World, health| {
+        let fields = [(type_name::<Health>(), TestValue::Seq(health))];
+        let registry = world.resource::<AppTypeRegistry>().read();
+        UntypedReflectDeserializer::new(&registry)
+            .deserialize(MapDeserializer::new(fields.into_iter()))
+            .unwrap()
+    };
+    let entity = world.spawn_empty().id();
+    let health = deserialize_health(world, &[TestValue::U64(5)]);
+    let handle = world.resource_scope(|world, mut server: Mut<RefCompServer>| {
+        server.insert_ref_comp_reflect(world, entity, health)
+    });
+    let handle = handle.unwrap();
+    assert!(handle.is_strong());
+    assert_eq!(world.get::<Health>(entity).unwrap().0, 5);
+
+    // A component that is already present is shared with other handles, so it is left as is.
+    let health = deserialize_health(world, &[TestValue::U64(9)]);
+    let handle2 = world.resource_scope(|world, mut server: Mut<RefCompServer>| {
+        server.insert_ref_comp_reflect(world, entity, health)
+    });
+    assert!(handle2.unwrap().is_strong());
+    assert_eq!(world.get::<Health>(entity).unwrap().0, 5);
+    assert!(world.resource::<Events<RefCompEdited>>().is_empty());
+
+    let missing = world.spawn_empty().id();
+    world.despawn(missing);
+    let result = world.resource_scope(|world, mut server: Mut<RefCompServer>| {
+        server.insert_ref_comp_reflect(world, missing, Box::new(Health(5)))
+    });
+    assert_eq!(
+        result.err(),
+        Some(RefCompReflectError::NoSuchEntity(missing))
+    );
+    app.update();
+
+    let world = &mut app.world;
+    assert!(world.entity(entity).contains::<Health>());
+    drop(handle);
+    app.update();
+
+    assert!(!app.world.entity(entity).contains::<Health>());
+}
+
+/// Tests that strong handle components survive being copied into another world through
+/// reflection, the way a scene is saved and spawned.
+#[test]
+fn test_scene_round_trip() {
+    let mut source = App::new();
+    source
+        .add_plugin(RefCompPlugin::default())
+        .register_type::<Foo>()
+        .register_ref_handle::<Foo>();
+
+    let world = &mut source.world;
+    let foo_ent = world.spawn_empty().id();
+    let handle = world.insert_ref_comp_from_world::<Foo>(foo_ent, None);
+    let holder = world.spawn(handle).id();
+    source.update();
+
+    let mut app = App::new();
+    app.add_plugin(RefCompPlugin::default())
+        .register_type::<Foo>()
+        .register_ref_component::<Foo>()
+        .register_ref_handle::<Foo>();
+    app.world.spawn_batch([Foo, Foo]);
+    let entity_map = copy_entities(&source.world, &mut app.world, &[foo_ent, holder]);
+    app.update();
+
+    let world = &mut app.world;
+    let new_foo_ent = entity_map.get(foo_ent).unwrap();
+    let new_holder = entity_map.get(holder).unwrap();
+    let handle = world.get::<RefCompHandle<Foo>>(new_holder).unwrap();
+    assert!(handle.is_strong());
+    assert_eq!(handle.id.entity, new_foo_ent);
+    assert_eq!(world.resource::<RefCompServer>().ref_count(&handle.id), 1);
+
+    // Handles loaded in a later frame are restored too, while the ones restored earlier are not
+    // counted again.
+    app.update();
+    let entity_map = copy_entities(&source.world, &mut app.world, &[foo_ent, holder]);
+    app.update();
+
+    let world = &mut app.world;
+    let handle = world
+        .get::<RefCompHandle<Foo>>(entity_map.get(holder).unwrap())
+        .unwrap();
+    assert!(handle.is_strong());
+    let server = world.resource::<RefCompServer>();
+    assert_eq!(server.ref_count(&handle.id), 1);
+    assert_eq!(server.ref_count_of::<Foo>(new_foo_ent), 1);
+
+    world.despawn(new_holder);
+    app.update();
+
+    assert!(!app.world.entity(new_foo_ent).contains::<Foo>());
+}
+
+/// Tests that remapping a strong handle component moves its count to the new entity.
+#[test]
+fn test_map_entities() {
+    let mut app = App::new();
+    app.add_plugin(RefCompPlugin::default())
+        .register_ref_handle::<Foo>();
+
+    let world = &mut app.world;
+    let old_ent = world.spawn_empty().id();
+    let handle = world.insert_ref_comp_from_world::<Foo>(old_ent, None);
+    let new_ent = world.spawn(Foo).id();
+    let holder = world.spawn(handle).id();
+    app.update();
+
+    let world = &mut app.world;
+    let mut entity_map = EntityMap::default();
+    entity_map.insert(old_ent, new_ent);
+    entity_map.insert(holder, holder);
+    let registry = world.resource::<AppTypeRegistry>().clone();
+    registry
+        .read()
+        .get(std::any::TypeId::of::<RefCompHandle<Foo>>())
+        .and_then(|registration| registration.data::<ReflectMapEntities>())
+        .unwrap()
+        .map_entities(world, &entity_map)
+        .unwrap();
+    app.update();
+
+    let world = &mut app.world;
+    let id = world.get::<RefCompHandle<Foo>>(holder).unwrap().id;
+    assert_eq!(id, RefCompHandleId::new::<Foo>(new_ent));
+    let server = world.resource::<RefCompServer>();
+    assert_eq!(server.ref_count(&id), 1);
+    assert_eq!(server.ref_count_of::<Foo>(old_ent), 0);
+    assert!(!world.entity(old_ent).contains::<Foo>());
+    assert!(world.entity(new_ent).contains::<Foo>());
+}
+
+/// Tests that remapping the handle of an entity spawned with `spawn_ref` moves its despawn
+/// policy to the new entity.
+#[test]
+fn test_map_spawn_ref() {
+    let mut world = World::new();
+    world.init_resource::<RefCompServer>();
+
+    let mut handle = world.spawn_ref(Foo);
+    let old_ent = handle.id.entity;
+    let new_ent = world.spawn(RefEntity).id();
+    world.flush_ref_comps();
+
+    let mut entity_map = EntityMap::default();
+    entity_map.insert(old_ent, new_ent);
+    handle.map_entities(&entity_map).unwrap();
+    world.flush_ref_comps();
+    assert!(!world.entity(old_ent).contains::<RefEntity>());
+    assert_eq!(world.resource::<RefCompServer>().ref_count(&handle.id), 1);
+
+    drop(handle);
+    world.flush_ref_comps();
+    assert!(world.get_entity(new_ent).is_none());
+    assert!(world.get_entity(old_ent).is_some());
+}
+
+/// Copies every reflected component of `entities` into new entities, then maps entity
+/// references, like `DynamicScene::write_to_world`.
+fn copy_entities(source: &World, destination: &mut World, entities: &[Entity]) -> EntityMap {
+    let registry = source.resource::<AppTypeRegistry>().clone();
+    let registry = registry.read();
+    let mut entity_map = EntityMap::default();
+    for &entity in entities {
+        let new_entity = destination.spawn_empty().id();
+        entity_map.insert(entity, new_entity);
+        for registration in registry.iter() {
+            let Some(reflect_component) = registration.data::<ReflectComponent>() else {
+                continue;
+            };
+            if let Some(component) = reflect_component.reflect(source.entity(entity)) {
+                let component = component.clone_value();
+                reflect_component
+                    .apply_or_insert(&mut destination.entity_mut(new_entity), &*component);
+            }
+        }
+    }
+    for registration in registry.iter() {
+        if let Some(map_entities) = registration.data::<ReflectMapEntities>() {
+            map_entities.map_entities(destination, &entity_map).unwrap();
+        }
+    }
+    entity_map
+}
+
+/// Tests that handles deserialize from their documented format, as Strong handles when read
+/// through the server's seed.
+#[test]
+fn test_handle_serde() {
+    let mut app = App::new();
+    app.add_plugin(RefCompPlugin::default())
+        .init_ref_component::<Baz>();
+
+    let world = &mut app.world;
+    let entity = world.spawn_empty().id();
+    let handle = Baz::ref_builder(entity).build_world(world);
+    assert_eq!(handle.id.type_id.name(), "game::Baz");
+
+    let fields = [
+        ("entity", TestValue::U64(entity.to_bits())),
+        ("component", TestValue::Str("game::Baz")),
+    ];
+    let server = world.resource::<RefCompServer>();
+    let loaded = server
+        .handle_seed::<Baz>()
+        .deserialize(MapDeserializer::new(fields.into_iter()))
+        .unwrap();
+    assert!(loaded.is_strong());
+    assert_eq!(loaded.id, handle.id);
+    assert_eq!(server.ref_count(&handle.id), 2);
+
+    let weak = RefCompHandle::<Baz>::deserialize(MapDeserializer::new(fields.into_iter()));
+    assert!(weak.unwrap().is_weak());
+    let wrong = RefCompHandle::<Foo>::deserialize(MapDeserializer::new(fields.into_iter()));
+    assert!(wrong.is_err());
+}
+
+/// Tests that a typed handle deserializes even if its type was not used yet in this session.
+#[test]
+fn test_handle_serde_unused_type() {
+    #[derive(Component)]
+    struct Unused;
+
+    let entity = Entity::from_raw(3);
+    let fields = [
+        ("entity", TestValue::U64(entity.to_bits())),
+        ("component", TestValue::Str(type_name::<Unused>())),
+    ];
+    let handle = RefCompHandle::<Unused>::deserialize(MapDeserializer::new(fields.into_iter()));
+    assert_eq!(handle.unwrap().id, RefCompHandleId::new::<Unused>(entity));
+}
+
+/// Tests that a snapshot survives a round trip through a binary format, and that restoring it
+/// reports what differs from the loaded world, including types that no longer exist.
+#[test]
+fn test_snapshot_restore() {
+    let mut app = App::new();
+    app.add_plugin(RefCompPlugin::default())
+        .init_ref_component::<Foo>();
+
+    let world = &mut app.world;
+    let [a, b, c] = [(); 3].map(|_| world.spawn_empty().id());
+    let _a = Foo::ref_builder(a).build_world(world);
+    let b1 = Foo::ref_builder(b).build_world(world);
+    let _b2 = b1.clone();
+    let _c = Foo::ref_builder(c).build_world(world);
+
+    let mut snapshot = world.resource::<RefCompServer>().snapshot();
+    assert_eq!(snapshot.types, vec![type_name::<Foo>()]);
+    let counts: Vec<_> = snapshot
+        .entries
+        .iter()
+        .map(|entry| (entry.entity, entry.count))
+        .collect();
+    assert_eq!(counts, vec![(a, 1), (b, 2), (c, 1)]);
+
+    // A type that was removed from the game since the snapshot was saved.
+    snapshot.types.push("game::Removed".to_owned());
+    snapshot.entries.push(RefCompSnapshotEntry {
+        entity: a,
+        component: "game::Removed".to_owned(),
+        count: 1,
+    });
+    let bytes = bincode::serialize(&snapshot).unwrap();
+    let snapshot: RefCompSnapshot = bincode::deserialize(&bytes).unwrap();
+
+    let mut loaded = App::new();
+    loaded
+        .add_plugin(RefCompPlugin::default())
+        .init_ref_component::<Foo>();
+    let world = &mut loaded.world;
+    assert_eq!(world.spawn(Foo).id(), a);
+    assert_eq!(world.spawn_empty().id(), b);
+    assert_eq!(world.spawn(Foo).id(), c);
+    let d = world.spawn(Foo).id();
+
+    world.resource_scope(|world, mut server: Mut<RefCompServer>| {
+        let a_id = RefCompHandleId::new::<Foo>(a);
+        let c_id = RefCompHandleId::new::<Foo>(c);
+        let d_id = RefCompHandleId::new::<Foo>(d);
+        let handles: Vec<RefCompHandle<Foo>> = [a_id, a_id, d_id]
+            .into_iter()
+            .map(|id| server.get_handle(id))
+            .collect();
+
+        let report = server.restore(world, &snapshot);
+        assert_eq!(report.unregistered_types, vec!["game::Removed"]);
+        assert_eq!(
+            report.missing_components,
+            vec![RefCompHandleId::new::<Foo>(b)]
+        );
+        assert_eq!(
+            report.count_mismatches,
+            vec![
+                RefCountMismatch {
+                    id: a_id,
+                    expected: 1,
+                    found: 2,
+                },
+                RefCountMismatch {
+                    id: c_id,
+                    expected: 1,
+                    found: 0,
+                },
+            ]
+        );
+        assert_eq!(report.unexpected_handles, vec![d_id]);
+        drop(handles);
+    });
+
+    loaded.update();
+    let world = &loaded.world;
+    assert!(world.get::<Foo>(c).is_none());
+    assert!(world.get::<Foo>(a).is_none());
+}
+
+/// A serialized value for deserializing test data without a data format.
+#[derive(Clone, Copy)]
+enum TestValue {
+    U64(u64),
+    Str(&'static str),
+    Seq(&'static [TestValue]),
+}
+
+impl<'de> IntoDeserializer<'de, value::Error> for TestValue {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for TestValue {
+    type Error = value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            TestValue::U64(value) => visitor.visit_u64(value),
+            TestValue::Str(value) => visitor.visit_borrowed_str(value),
+            TestValue::Seq(values) => {
+                visitor.visit_seq(SeqDeserializer::new(values.iter().copied()))
+            }
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// Tests that handle ids are keyed by component type and that the type's stable name
+/// resolves back to the same id.
+#[test]
+fn test_handle_id_type() {
+    let entity = Entity::from_raw(0);
+    let foo_id = RefCompHandleId::new::<Foo>(entity);
+    let bar_id = RefCompHandleId::new::<Bar>(entity);
+
+    assert_eq!(foo_id, RefCompHandleId::new::<Foo>(entity));
+    assert_ne!(foo_id, bar_id);
+
+    let name = foo_id.type_id.name();
+    assert!(name.ends_with("Foo"));
+    assert_eq!(RefCompTypeId::from_name(name), Some(foo_id.type_id));
+}
+
+/// Tests that a stable name can't be given to two different types.
+#[test]
+#[should_panic(expected = "already used by a different type")]
+fn test_duplicate_type_name() {
+    struct First;
+    struct Second;
+
+    RefCompTypeId::of::<First>().set_name("test::Duplicate");
+    RefCompTypeId::of::<First>().set_name("test::Duplicate");
+    RefCompTypeId::of::<Second>().set_name("test::Duplicate");
+}
+
+#[derive(ScheduleLabel, Clone, Debug, PartialEq, Eq, Hash)]
+struct ReleaseSchedule;
+
+#[derive(SystemSet, Clone, Debug, PartialEq, Eq, Hash)]
+struct ReleaseSet;
+
+#[derive(Resource)]
+struct FooHandleOpt(Option<RefCompHandle<Foo>>);
+
+#[derive(Resource, Default)]
+struct FooReleased(bool);
+
+#[derive(Component, Default, Reflect, RefComponent)]
+#[reflect(Component)]
+struct Foo;
+
+#[derive(Component, RefComponent)]
+#[ref_comp(
+    insert = Baz::new,
+    edit = Baz::bump,
+    release = "delay(1)",
+    despawn_empty,
+    name = "game::Baz"
+)]
+struct Baz(u32);
+
+impl Baz {
+    fn new(_world: &mut World, _entity: Entity) -> Self {
+        Baz(1)
+    }
+
+    fn bump(_world: &mut World, _entity: Entity, baz: &mut Baz) {
+        baz.0 += 1;
+    }
+}
+
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+struct Health(u32);
+
+#[derive(Resource)]
+struct EntityRef(Entity);
+
+#[derive(Resource)]
+#[allow(dead_code)]
+struct FooHandleRes1(RefCompHandle<Foo>);
+
+#[derive(Resource)]
+#[allow(dead_code)]
+struct FooHandleRes2(RefCompHandle<Foo>);
+
+#[derive(Component, Default)]
+struct Bar {
+    string: String,
+    integer: u32,
+}
+
+#[derive(Resource)]
+#[allow(dead_code)]
+struct BarHandleRes1(RefCompHandle<Bar>);
+
+#[derive(Resource)]
+#[allow(dead_code)]
+struct BarHandleRes2(RefCompHandle<Bar>);
+
+#[derive(Resource)]
+#[allow(dead_code)]
+struct BundleHandleRes(RefCompHandle<(Foo, Bar)>);
+
+#[derive(Resource)]
+#[allow(dead_code)]
+struct RefEntityRes(RefCompHandle<RefEntity>);
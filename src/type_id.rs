@@ -0,0 +1,149 @@
+use std::{
+    any::{type_name, TypeId},
+    sync::OnceLock,
+};
+
+use bevy::{
+    log::warn,
+    utils::{HashMap, HashSet},
+};
+use parking_lot::RwLock;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Identifies the component type a [`RefCompHandleId`](crate::RefCompHandleId) refers to.
+///
+/// Backed by a [`TypeId`], so it is cheap to copy, hash and compare. Its stable name is only
+/// looked up when the id is printed or serialized.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct RefCompTypeId(TypeId);
+
+impl RefCompTypeId {
+    /// Get the id of `T`, registering its stable name for serialization.
+    #[inline]
+    pub fn of<T: 'static>() -> Self {
+        Self::from_type_id(TypeId::of::<T>(), type_name::<T>())
+    }
+
+    /// Get the id of a type only known at runtime, registering `name` as its stable name.
+    pub(crate) fn from_type_id(type_id: TypeId, name: &'static str) -> Self {
+        if !type_names().read().by_id.contains_key(&type_id) {
+            let mut type_names = type_names().write();
+            if type_names.insert(type_id, name).is_err() {
+                type_names.by_id.insert(type_id, name);
+                warn!(
+                    "`{name}` is the name of two different types, handles serialized with it \
+                    resolve to the first one. Give them stable names with \
+                    `RefCompRegistration::with_name`."
+                );
+            }
+        }
+        RefCompTypeId(type_id)
+    }
+
+    /// Replaces the stable name of this type, for example with one that doesn't change when the
+    /// type is moved to another module. The previous name can still be looked up.
+    ///
+    /// Names are shared by every [`App`](bevy::prelude::App) in the process.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is already the name of a different type.
+    pub fn set_name(&self, name: &'static str) {
+        let mut type_names = type_names().write();
+        if type_names.insert(self.0, name).is_err() {
+            panic!("the ref component type name `{name}` is already used by a different type");
+        }
+        type_names.named.insert(self.0);
+    }
+
+    /// The stable name to write this type with, warning the first time a type without an
+    /// explicitly set name is written.
+    pub(crate) fn saved_name(&self) -> &'static str {
+        let unnamed = !type_names().read().named.contains(&self.0);
+        if unnamed && type_names().write().named.insert(self.0) {
+            warn!(
+                "`{}` is serialized under its type name, which can change between builds. Give it \
+                a stable name with `RefCompRegistration::with_name`.",
+                self.name()
+            );
+        }
+        self.name()
+    }
+
+    /// Look up a previously registered type by its stable name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        type_names()
+            .read()
+            .by_name
+            .get(name)
+            .copied()
+            .map(RefCompTypeId)
+    }
+
+    #[inline]
+    pub fn type_id(&self) -> TypeId {
+        self.0
+    }
+
+    /// The stable name of this type, used when serializing. Defaults to its type name.
+    pub fn name(&self) -> &'static str {
+        type_names()
+            .read()
+            .by_id
+            .get(&self.0)
+            .copied()
+            .unwrap_or("<unregistered>")
+    }
+}
+
+impl std::fmt::Debug for RefCompTypeId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Serialize for RefCompTypeId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.saved_name())
+    }
+}
+
+impl<'de> Deserialize<'de> for RefCompTypeId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        RefCompTypeId::from_name(&name).ok_or_else(|| {
+            de::Error::custom(format!(
+                "ref component type `{name}` has not been registered in this session"
+            ))
+        })
+    }
+}
+
+#[derive(Default)]
+struct TypeNames {
+    by_id: HashMap<TypeId, &'static str>,
+    by_name: HashMap<&'static str, TypeId>,
+    /// Types given a stable name with [`RefCompTypeId::set_name`], or already warned about
+    /// being serialized without one.
+    named: HashSet<TypeId>,
+}
+
+impl TypeNames {
+    /// Makes `name` the stable name of `type_id`. Fails without changing anything if `name`
+    /// already belongs to a different type.
+    fn insert(&mut self, type_id: TypeId, name: &'static str) -> Result<(), ()> {
+        match self.by_name.get(name) {
+            Some(existing) if *existing != type_id => Err(()),
+            _ => {
+                self.by_id.insert(type_id, name);
+                self.by_name.insert(name, type_id);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn type_names() -> &'static RwLock<TypeNames> {
+    static TYPE_NAMES: OnceLock<RwLock<TypeNames>> = OnceLock::new();
+    TYPE_NAMES.get_or_init(Default::default)
+}